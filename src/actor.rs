@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    io, mem,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use indexmap::{IndexMap, IndexSet};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    ClientBook, Counters,
+    client::{ClientAccount, TxOutcome},
+    snapshot::AccountSnapshot,
+    storage::{self, MemoryStorage, shard},
+    transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// How many commands may wait for an actor before senders wait too.
+const BACKLOG: usize = 1024;
+
+/// An async handle to a book, whose accounts are split into shards, each
+/// owned by an actor task. Cloning the handle is cheap, and every clone
+/// talks to the same actors.
+///
+/// Each client's transactions apply in the order they were sent, while
+/// clients of different shards are processed concurrently.
+#[derive(Clone, Debug)]
+pub struct BookHandle {
+    shards: Arc<[mpsc::Sender<Command>]>,
+    index: Arc<Mutex<Index>>,
+}
+
+enum Command {
+    Append {
+        tx: Transaction,
+        reply: oneshot::Sender<io::Result<TxOutcome>>,
+    },
+    Account {
+        client: ClientId,
+        reply: oneshot::Sender<Option<AccountSnapshot>>,
+    },
+    Stop {
+        reply: oneshot::Sender<(IndexMap<ClientId, ClientAccount>, Counters)>,
+    },
+}
+
+/// What all actors share, so that transaction IDs stay unique across
+/// the whole book.
+#[derive(Debug, Default)]
+struct Index {
+    /// The client that logged each transaction ID.
+    owners: HashMap<TransactionId, ClientId>,
+    /// Clients in the order they were first seen.
+    clients: IndexSet<ClientId>,
+}
+
+impl BookHandle {
+    /// Hands the accounts of `book` over to `shards` actor tasks, spawned
+    /// on the current tokio runtime.
+    ///
+    /// Journaled books only apply one transaction at a time, and are refused.
+    ///
+    /// # Panics
+    ///
+    /// When called outside of a tokio runtime.
+    pub fn spawn(book: ClientBook, shards: NonZeroUsize) -> io::Result<Self> {
+        if book.journal.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "journaled books cannot be shared between actors",
+            ));
+        }
+
+        let cross_client = book.cross_client;
+        let clients = book.into_clients();
+        let index = Arc::new(Mutex::new(Index {
+            owners: storage::owners(&clients),
+            clients: clients.keys().copied().collect(),
+        }));
+
+        let count = shards.get();
+        let mut split: Vec<IndexMap<_, _>> = (0..count).map(|_| IndexMap::new()).collect();
+        for (id, account) in clients {
+            split[shard(id, count)].insert(id, account);
+        }
+
+        let shards = split
+            .into_iter()
+            .map(|clients| {
+                let (sender, commands) = mpsc::channel(BACKLOG);
+                let mut book = ClientBook::with_storage(MemoryStorage::new(clients));
+                book.set_cross_client_policy(cross_client);
+
+                tokio::spawn(run(book, Arc::clone(&index), commands));
+                sender
+            })
+            .collect();
+
+        Ok(Self { shards, index })
+    }
+
+    /// Appends one transaction to the book, see [`ClientBook::append_tx`],
+    /// once every transaction sent earlier for the same client applied.
+    pub async fn append_tx(&self, tx: Transaction) -> io::Result<TxOutcome> {
+        let (reply, outcome) = oneshot::channel();
+        self.send(tx.client_id, Command::Append { tx, reply })
+            .await?;
+        outcome.await.map_err(|_| stopped())?
+    }
+
+    /// The current state of a client's account, if the client is known.
+    ///
+    /// **NOTE:** Queries go through the same queue as transactions, so
+    /// they see every transaction sent before them, and they never hold
+    /// anything writers would wait on.
+    pub async fn account(&self, client: ClientId) -> io::Result<Option<AccountSnapshot>> {
+        let (reply, account) = oneshot::channel();
+        self.send(client, Command::Account { client, reply })
+            .await?;
+        account.await.map_err(|_| stopped())
+    }
+
+    /// Stops all actors once they are done with what was sent so far,
+    /// and gathers their accounts back into a single book.
+    ///
+    /// Other clones of this handle fail from then on.
+    pub async fn shutdown(self) -> io::Result<ClientBook> {
+        let mut stopping = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            let (reply, clients) = oneshot::channel();
+            shard
+                .send(Command::Stop { reply })
+                .await
+                .map_err(|_| stopped())?;
+            stopping.push(clients);
+        }
+
+        let mut shards = Vec::with_capacity(stopping.len());
+        let mut counters = Counters::default();
+        for shard in stopping {
+            let (clients, processed) = shard.await.map_err(|_| stopped())?;
+            shards.push(clients);
+            counters += processed;
+        }
+
+        // Nothing applies anymore, so the order is final.
+        let order = mem::take(
+            &mut self
+                .index
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clients,
+        );
+
+        let count = shards.len();
+        let clients = order
+            .into_iter()
+            .filter_map(|id| Some((id, shards[shard(id, count)].swap_remove(&id)?)))
+            .collect();
+
+        let mut book = ClientBook::with_storage(MemoryStorage::new(clients));
+        book.counters = counters;
+        Ok(book)
+    }
+
+    async fn send(&self, client: ClientId, command: Command) -> io::Result<()> {
+        self.shards[shard(client, self.shards.len())]
+            .send(command)
+            .await
+            .map_err(|_| stopped())
+    }
+}
+
+/// Applies commands to the actor's shard of the book, until it is
+/// told to stop or every handle is gone.
+async fn run(
+    mut book: ClientBook,
+    index: Arc<Mutex<Index>>,
+    mut commands: mpsc::Receiver<Command>,
+) {
+    // **NOTE:** Replies fail when the caller stopped waiting, which is
+    // none of our business.
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Append { tx, reply } => {
+                let _ = reply.send(append(&mut book, &index, tx));
+            }
+            Command::Account { client, reply } => {
+                let account = book
+                    .storage
+                    .clients()
+                    .get(&client)
+                    .map(AccountSnapshot::from);
+                let _ = reply.send(account);
+            }
+            Command::Stop { reply } => {
+                let counters = book.counters();
+                let _ = reply.send((book.into_clients(), counters));
+                return;
+            }
+        }
+    }
+}
+
+/// Appends a transaction to an actor's shard, checking its ID against
+/// the whole book.
+///
+/// **NOTE:** A new ID is reserved for the client before the transaction
+/// applies, and released if it doesn't, so the index is only locked for
+/// a couple of lookups and actors never wait on each other's transactions.
+/// Two clients racing for the same ID can't both get it, and the loser is
+/// refused even if the winner's transaction ends up not applying.
+///
+/// The index sits behind a std mutex, which is fine within async tasks as
+/// it is never held across an await, and only for as long as a lookup.
+fn append(book: &mut ClientBook, index: &Mutex<Index>, tx: Transaction) -> io::Result<TxOutcome> {
+    let (id, client) = (tx.id, tx.client_id);
+
+    let reserved = {
+        let mut index = index.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&owner) = index.owners.get(&id)
+            && owner != client
+        {
+            let outcome = book.cross_client.outcome(&tx, owner);
+            book.counters.record(&outcome);
+            return Ok(outcome);
+        }
+
+        index.clients.insert(client);
+        tx.is_logged() && index.owners.insert(id, client).is_none()
+    };
+
+    let outcome = book.append_tx(tx);
+    if reserved && !matches!(outcome, Ok(TxOutcome::Applied)) {
+        let mut index = index.lock().unwrap_or_else(PoisonError::into_inner);
+        index.owners.remove(&id);
+    }
+
+    outcome
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("book actors stopped")
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::{client::TransactionError, transaction::TransactionType};
+
+    fn tx(ty: TransactionType, client: u16, id: u32) -> Transaction {
+        Transaction {
+            ty,
+            client_id: ClientId::new(client),
+            id: TransactionId::new(id),
+        }
+    }
+
+    #[test]
+    fn applies_concurrent_writers_like_a_book() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let handle = BookHandle::spawn(ClientBook::default(), NonZeroUsize::new(3).unwrap())
+                .expect("book is not journaled");
+
+            // A writer per client, all at once.
+            let writers: Vec<_> = (1..=5u16)
+                .map(|client| {
+                    let handle = handle.clone();
+                    tokio::spawn(async move {
+                        for i in 0..10 {
+                            let id = u32::from(client) * 100 + i;
+                            let deposit = TransactionType::Deposit { amount: dec!(1.5) };
+                            let outcome = handle.append_tx(tx(deposit, client, id)).await.unwrap();
+                            assert_eq!(outcome, TxOutcome::Applied);
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.await.unwrap();
+            }
+
+            let dispute = TransactionType::Dispute { amount: None };
+            assert_eq!(
+                handle.append_tx(tx(dispute, 2, 100)).await.unwrap(),
+                TxOutcome::Ignored(crate::client::IgnoreReason::CrossClientDispute {
+                    owner: ClientId::new(1)
+                })
+            );
+
+            let deposit = TransactionType::Deposit { amount: dec!(1.0) };
+            assert_eq!(
+                handle.append_tx(tx(deposit, 4, 305)).await.unwrap(),
+                TxOutcome::Rejected(TransactionError::DuplicateTransactionId {
+                    owner: ClientId::new(3)
+                })
+            );
+
+            // IDs of transactions that didn't apply are free to take.
+            let withdrawal = TransactionType::Withdrawal { amount: dec!(100) };
+            assert_eq!(
+                handle.append_tx(tx(withdrawal, 1, 900)).await.unwrap(),
+                TxOutcome::Rejected(TransactionError::NotEnoughBalance)
+            );
+            let deposit = TransactionType::Deposit { amount: dec!(0) };
+            assert_eq!(
+                handle.append_tx(tx(deposit, 2, 900)).await.unwrap(),
+                TxOutcome::Applied
+            );
+
+            let account = handle.account(ClientId::new(3)).await.unwrap().unwrap();
+            assert_eq!(account.available, dec!(15.0));
+            assert_eq!(account.log.len(), 10);
+            assert!(handle.account(ClientId::new(9)).await.unwrap().is_none());
+
+            let clients = handle.shutdown().await.unwrap().into_clients();
+            assert_eq!(clients.len(), 5);
+            assert!(
+                clients
+                    .values()
+                    .all(|account| account.total() == dec!(15.0))
+            );
+        });
+    }
+}
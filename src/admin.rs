@@ -0,0 +1,377 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Component, Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde::Serialize;
+
+use crate::{
+    ClientBook,
+    client::TxOutcome,
+    snapshot::{self, AccountSnapshot, EntrySnapshot, Snapshot},
+    storage::Storage,
+    transaction::{Authorization, ClientId, TransactionId, TransactionType},
+};
+
+/// Listens for admins on a Unix socket at `path`, replacing the one a
+/// previous run left behind.
+///
+/// The socket is only accessible to the engine's own user, as admin
+/// commands can read every client's log.
+///
+/// **NOTE:** Binding creates the socket with whatever permissions the
+/// umask leaves, so it is bound within a fresh directory only we can
+/// enter, restricted, then moved into place. Only sockets are replaced,
+/// a typo must never delete a file.
+pub fn listen(path: &Path) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+
+    let mut private = path.as_os_str().to_owned();
+    private.push(format!(".{}", process::id()));
+    let private = PathBuf::from(private);
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let bound = private.join("s");
+    let listener = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&bound);
+    let removed = fs::remove_dir(&private);
+
+    let listener = listener?;
+    removed?;
+    Ok(listener)
+}
+
+/// Accepts admin connections on `listener` until it fails, serving each
+/// one on its own thread, all of them sharing `book`.
+///
+/// The protocol is line based, one command per line:
+///
+/// ```text
+/// log <client>       the client's log, with the dispute state of each transaction
+/// audit <client>     the client's administrative transactions
+/// disputes           every active dispute, across the book
+/// snapshot <name>    saves a snapshot of the whole book as <name>, in `snapshots`
+/// counters           how many transactions were processed, by outcome
+///
+/// freeze <client> <operator> [<reason>]
+/// unfreeze <client> <operator> [<reason>]
+/// unlock <client> <operator> [<reason>]
+/// close <client> <operator> [<reason>]
+/// ```
+///
+/// The last ones are administrative transactions, see
+/// [`ClientBook::administer`], which are only ever taken from here. They
+/// are answered with the transaction, along with the ID its account gave
+/// it, when applied, or `error <code> <message>` when ignored or rejected.
+///
+/// Every command is answered with a JSON object per line, if it has
+/// anything to show, followed by `end`. Commands that fail are answered
+/// with a single `error <message>` line instead.
+///
+/// **NOTE:** Snapshots are only ever saved as plain file names within
+/// the `snapshots` directory, so admins can't overwrite arbitrary files.
+/// Without one, `snapshot` is refused.
+pub fn serve<S>(
+    listener: UnixListener,
+    book: Arc<Mutex<ClientBook<S>>>,
+    snapshots: Option<PathBuf>,
+) -> io::Result<()>
+where
+    S: Storage + Send + 'static,
+{
+    let snapshots = Arc::new(snapshots);
+    loop {
+        let (stream, _) = listener.accept()?;
+        let book = Arc::clone(&book);
+        let snapshots = Arc::clone(&snapshots);
+
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, &book, snapshots.as_deref()) {
+                eprintln!("admin connection failed: {err}");
+            }
+        });
+    }
+}
+
+/// An active dispute, as answered by `disputes`.
+#[derive(Debug, Serialize)]
+struct Dispute {
+    client: ClientId,
+    tx: TransactionId,
+    /// Formatted with 4 decimal places, as in account balances.
+    held: String,
+}
+
+/// Answers every command an admin sends, until they disconnect.
+fn handle<S: Storage>(
+    stream: UnixStream,
+    book: &Mutex<ClientBook<S>>,
+    snapshots: Option<&Path>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return writer.flush();
+        }
+
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        // **NOTE:** Answers are built in full before being written, so a
+        // command failing midway never leaves a partial answer behind.
+        match respond(command, book, snapshots) {
+            Ok(lines) => {
+                for line in lines {
+                    writeln!(writer, "{line}")?;
+                }
+                writeln!(writer, "end")?;
+            }
+            Err(err) => writeln!(writer, "error {err}")?,
+        }
+        writer.flush()?;
+    }
+}
+
+/// The lines answering a single command, before `end`.
+fn respond<S: Storage>(
+    command: &str,
+    book: &Mutex<ClientBook<S>>,
+    snapshots: Option<&Path>,
+) -> io::Result<Vec<String>> {
+    let mut book = book
+        .lock()
+        .map_err(|_| io::Error::other("book is unavailable after an earlier failure"))?;
+
+    let (name, arg) = command
+        .split_once(char::is_whitespace)
+        .map_or((command, ""), |(name, arg)| (name, arg.trim()));
+
+    match (name, arg) {
+        ("log", client) => {
+            let client = client_id(client)?;
+            let log = book
+                .log(client)?
+                .ok_or_else(|| invalid(format!("unknown client {client}")))?;
+            log.into_iter()
+                .map(|entry| json(&EntrySnapshot::from(&entry)))
+                .collect()
+        }
+        ("audit", client) => {
+            let client = client_id(client)?;
+            let account = book
+                .account(client)?
+                .ok_or_else(|| invalid(format!("unknown client {client}")))?;
+            account.audit().iter().map(json).collect()
+        }
+        ("disputes", "") => book
+            .disputes()?
+            .into_iter()
+            .map(|(client, tx, held)| {
+                json(&Dispute {
+                    client,
+                    tx,
+                    held: format!("{held:.4}"),
+                })
+            })
+            .collect(),
+        ("snapshot", "") => Err(invalid("snapshot expects a name")),
+        ("snapshot", name) => {
+            let dir = snapshots.ok_or_else(|| invalid("snapshots are disabled"))?;
+            let path = snapshot_path(dir, name)?;
+
+            let mut clients = vec![];
+            book.for_each_account(|account| {
+                clients.push(AccountSnapshot::from(account));
+                Ok(())
+            })?;
+
+            let snapshot = Snapshot {
+                version: snapshot::VERSION,
+                clients,
+            };
+            snapshot.save(&path)?;
+            Ok(vec![])
+        }
+        ("counters", "") => Ok(vec![json(&book.counters())?]),
+        ("freeze" | "unfreeze" | "unlock" | "close", args) => {
+            let ty = match name {
+                "freeze" => TransactionType::Freeze,
+                "unfreeze" => TransactionType::Unfreeze,
+                "unlock" => TransactionType::Unlock,
+                _ => TransactionType::Close,
+            };
+            let (client, authorization) = order(ty, args)?;
+
+            match book.administer(client, ty, authorization)? {
+                // Answered with the transaction, so admins learn its ID.
+                TxOutcome::Applied => book
+                    .account(client)?
+                    .iter()
+                    .filter_map(|account| account.audit().last())
+                    .map(json)
+                    .collect(),
+                outcome => Err(invalid(format!("{} {outcome}", outcome.code()))),
+            }
+        }
+        _ => Err(invalid(format!("unknown command {command:?}"))),
+    }
+}
+
+/// The client and authorization of an administrative transaction of type
+/// `ty`, out of its arguments, `<client> <operator> [<reason>]`, separated
+/// by any whitespace.
+fn order(ty: TransactionType, mut args: &str) -> io::Result<(ClientId, Authorization)> {
+    let (Some(client), Some(operator)) = (next_arg(&mut args), next_arg(&mut args)) else {
+        return Err(invalid(format!(
+            "{} expects a client and an operator",
+            ty.name()
+        )));
+    };
+
+    let authorization = Authorization {
+        operator: operator.to_owned(),
+        reason: args.trim().to_owned(),
+    };
+
+    Ok((client_id(client)?, authorization))
+}
+
+fn client_id(client: &str) -> io::Result<ClientId> {
+    client
+        .parse()
+        .map(ClientId::new)
+        .map_err(|_| invalid(format!("invalid client {client:?}")))
+}
+
+/// Takes the next whitespace separated argument off `args`.
+fn next_arg<'a>(args: &mut &'a str) -> Option<&'a str> {
+    let rest = args.trim_start();
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let (arg, rest) = rest.split_at(end);
+    *args = rest;
+    (!arg.is_empty()).then_some(arg)
+}
+
+/// Where a snapshot named `name` is saved, refusing anything but a plain
+/// file name, which could escape `dir`.
+fn snapshot_path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(file)), None) if !name.starts_with('.') => Ok(dir.join(file)),
+        _ => Err(invalid(format!("invalid snapshot name {name:?}"))),
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> io::Result<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn inspects_a_running_book() {
+        let dir = env::temp_dir().join(format!("payx-admin-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("admin.sock");
+
+        let book = ClientBook::from_reader(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,2,5.0\n\
+             dispute,1,2,1.5\n\
+             deposit,2,3,3.0\n\
+             withdrawal,2,4,9.0\n\
+             bogus,2,5,1.0\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let listener = listen(&socket).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!dir.join(format!("admin.sock.{}", process::id())).exists());
+
+        let snapshots = Some(dir.clone());
+        thread::spawn(move || serve(listener, Arc::new(Mutex::new(book)), snapshots));
+
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        write!(
+            stream,
+            "log 1\nlog 3\ndisputes\ncounters\nsnapshot book.json\n\
+             snapshot ../book.json\nsnapshot /tmp/book.json\nsnapshot .hidden\n\
+             freeze 2\trisk-7   suspicious activity\nlog 2\naudit 2\nunfreeze  2 risk-7\n\
+             unlock 2 risk-7\nfreeze 2\nclose 9 ops-1\nlog 9\nhelp\n"
+        )
+        .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let lines: Vec<_> = BufReader::new(stream)
+            .lines()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines.len(), 25, "{lines:#?}");
+
+        assert!(lines[0].contains(r#""tx":1"#));
+        assert!(lines[1].contains(r#""state":"disputed""#));
+        assert_eq!(lines[2], "end");
+        assert_eq!(lines[3], "error unknown client 3");
+        assert_eq!(lines[4], r#"{"client":1,"tx":2,"held":"1.5000"}"#);
+        assert_eq!(lines[5], "end");
+        assert_eq!(
+            lines[6],
+            r#"{"applied":4,"ignored":0,"rejected":1,"malformed":1}"#
+        );
+        assert_eq!(lines[7], "end");
+        assert_eq!(lines[8], "end");
+        assert_eq!(lines[9], r#"error invalid snapshot name "../book.json""#);
+        assert_eq!(lines[10], r#"error invalid snapshot name "/tmp/book.json""#);
+        assert_eq!(lines[11], r#"error invalid snapshot name ".hidden""#);
+        let freeze = r#"{"type":"freeze","client":2,"id":1,"operator":"risk-7","reason":"suspicious activity"}"#;
+        assert_eq!(lines[12], freeze);
+        assert_eq!(lines[13], "end");
+        // Administrative transactions stay out of the log.
+        assert!(lines[14].contains(r#""tx":3"#));
+        assert_eq!(lines[15], "end");
+        assert_eq!(lines[16], freeze);
+        assert_eq!(lines[17], "end");
+        assert!(lines[18].contains(r#""type":"unfreeze","client":2,"id":2"#));
+        assert_eq!(lines[19], "end");
+        assert_eq!(lines[20], "error not_locked account is not locked");
+        assert_eq!(lines[21], "error freeze expects a client and an operator");
+        assert_eq!(lines[22], "error unknown_client client is unknown");
+        assert_eq!(lines[23], "error unknown client 9");
+        assert!(lines[24].starts_with("error unknown command"));
+
+        let saved = Snapshot::read(fs::File::open(dir.join("book.json")).unwrap()).unwrap();
+        assert!(ClientBook::from_snapshot(saved).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
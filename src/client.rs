@@ -1,10 +1,12 @@
-use std::ops::Neg;
+use std::{convert::Infallible, ops::Neg, str::FromStr};
 
 use indexmap::IndexMap;
 use rust_decimal::Decimal;
-use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, ser::SerializeStruct};
 
-use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
+use crate::transaction::{
+    AdminTx, Authorization, ClientId, Transaction, TransactionId, TransactionType,
+};
 
 /// A transaction error.
 ///
@@ -16,12 +18,256 @@ use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
 pub enum TransactionError {
     #[error("account is locked")]
     LockedAccount,
+    #[error("account is frozen")]
+    FrozenAccount,
+    #[error("account is closed")]
+    ClosedAccount,
     #[error("not enough balance to withdraw")]
     NotEnoughBalance,
-    #[error("duplicate transaction ids")]
-    DuplicateTransactionId,
+    #[error("transaction id was already used by client {owner}")]
+    DuplicateTransactionId {
+        /// The client that first used the ID.
+        owner: ClientId,
+    },
     #[error("transactions can only have positive amounts")]
     AmountCannotBeNegative,
+    #[error("disputed amount must be greater than zero")]
+    InvalidDisputeAmount,
+    #[error("disputed amount exceeds what is left of the original transaction")]
+    DisputeExceedsAmount,
+    #[error("transaction was already charged back")]
+    AlreadyChargedBack,
+    #[error("dispute of referenced transaction was already resolved")]
+    AlreadyResolved,
+    /// A reason to ignore a transaction, which a policy asks to treat as
+    /// a failure instead, see [`crate::CrossClientPolicy`].
+    #[error(transparent)]
+    Escalated(IgnoreReason),
+    #[error("administrative transactions are only taken from operators")]
+    AdminOnly,
+    #[error("administrative transactions need an operator reference")]
+    MissingOperator,
+    #[error("only accounts without any balance can be closed")]
+    BalanceNotZero,
+}
+
+impl TransactionError {
+    /// A stable, machine-readable code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::LockedAccount => "locked_account",
+            Self::FrozenAccount => "frozen_account",
+            Self::ClosedAccount => "closed_account",
+            Self::NotEnoughBalance => "not_enough_balance",
+            Self::DuplicateTransactionId { .. } => "duplicate_transaction_id",
+            Self::AmountCannotBeNegative => "amount_cannot_be_negative",
+            Self::InvalidDisputeAmount => "invalid_dispute_amount",
+            Self::DisputeExceedsAmount => "dispute_exceeds_amount",
+            Self::AlreadyChargedBack => "already_charged_back",
+            Self::AlreadyResolved => "already_resolved",
+            Self::Escalated(reason) => reason.code(),
+            Self::AdminOnly => "admin_only",
+            Self::MissingOperator => "missing_operator",
+            Self::BalanceNotZero => "balance_not_zero",
+        }
+    }
+}
+
+/// Why a transaction was ignored.
+///
+/// Unlike [`TransactionError`], these are not failures. Partners send
+/// disputes for transactions we don't know of, or resolutions for
+/// disputes that never started, and the right thing is to carry on.
+/// Still, it's useful to know why a row had no effect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum IgnoreReason {
+    #[error("referenced transaction is unknown")]
+    UnknownTarget,
+    #[error("referenced transaction cannot be disputed")]
+    NotDisputable,
+    #[error("referenced transaction is already fully disputed")]
+    AlreadyDisputed,
+    #[error("referenced transaction is not in dispute")]
+    NotInDispute,
+    #[error("referenced transaction belongs to client {owner}")]
+    CrossClientDispute {
+        /// The client whose log holds the referenced transaction.
+        owner: ClientId,
+    },
+    #[error("account is not locked")]
+    NotLocked,
+    #[error("account is not frozen")]
+    NotFrozen,
+    #[error("only active accounts can be frozen")]
+    NotActive,
+    #[error("client is unknown")]
+    UnknownClient,
+}
+
+impl IgnoreReason {
+    /// A stable, machine-readable code for this reason.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnknownTarget => "unknown_target",
+            Self::NotDisputable => "not_disputable",
+            Self::AlreadyDisputed => "already_disputed",
+            Self::NotInDispute => "not_in_dispute",
+            Self::CrossClientDispute { .. } => "cross_client_dispute",
+            Self::NotLocked => "not_locked",
+            Self::NotFrozen => "not_frozen",
+            Self::NotActive => "not_active",
+            Self::UnknownClient => "unknown_client",
+        }
+    }
+}
+
+/// The outcome of appending a transaction to an account.
+#[must_use]
+#[derive(Debug, PartialEq, Eq)]
+pub enum TxOutcome {
+    /// The transaction took effect.
+    Applied,
+    /// The transaction is valid, but had no effect.
+    Ignored(IgnoreReason),
+    /// The transaction was refused.
+    Rejected(TransactionError),
+}
+
+impl TxOutcome {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, Self::Applied)
+    }
+
+    /// A stable, machine-readable code for this outcome.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Applied => "applied",
+            Self::Ignored(reason) => reason.code(),
+            Self::Rejected(err) => err.code(),
+        }
+    }
+}
+
+impl std::fmt::Display for TxOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Applied => f.write_str("transaction applied"),
+            Self::Ignored(reason) => reason.fmt(f),
+            Self::Rejected(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<IgnoreReason> for TxOutcome {
+    fn from(reason: IgnoreReason) -> Self {
+        Self::Ignored(reason)
+    }
+}
+
+impl From<TransactionError> for TxOutcome {
+    fn from(err: TransactionError) -> Self {
+        Self::Rejected(err)
+    }
+}
+
+/// Where a logged transaction is in its dispute lifecycle.
+///
+/// ```text
+/// Undisputed -> Disputed -> Resolved -> Disputed -> ...
+///                        -> ChargedBack
+/// ```
+///
+/// A resolved transaction can be disputed again, but a chargeback is final.
+///
+/// **NOTE:** Re-disputes are on purpose, the engine always allowed them.
+/// Resolving only ends the current dispute, it doesn't settle the
+/// transaction for good the way a chargeback does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DisputeState {
+    #[default]
+    Undisputed,
+    Disputed {
+        /// The amount currently held by the dispute.
+        held: Decimal,
+    },
+    Resolved,
+    ChargedBack {
+        /// The amount reversed by the chargeback.
+        amount: Decimal,
+    },
+}
+
+impl DisputeState {
+    /// The amount held by an active dispute, zero otherwise.
+    pub fn held(&self) -> Decimal {
+        match self {
+            Self::Disputed { held } => *held,
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+/// What an account is allowed to do.
+///
+/// ```text
+/// Active <-> Frozen
+///   |          |
+///   +----------+--> Locked --> Active
+///   |          |       |
+///   +----------+-------+--> Closed
+/// ```
+///
+/// Accounts are locked by chargebacks, everything else is up to
+/// administrative transactions, see [`crate::transaction::Authorization`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    /// Anything goes.
+    #[default]
+    Active,
+    /// Withdrawals are blocked, while deposits and disputes still apply.
+    Frozen,
+    /// Only unlocks and closing apply, until risk reviewed the account.
+    Locked,
+    /// Nothing applies anymore, for good.
+    Closed,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown account status {0:?}, expected active, frozen, locked or closed")]
+pub struct UnknownAccountStatus(String);
+
+impl FromStr for AccountStatus {
+    type Err = UnknownAccountStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(Self::Active),
+            "frozen" => Ok(Self::Frozen),
+            "locked" => Ok(Self::Locked),
+            "closed" => Ok(Self::Closed),
+            _ => Err(UnknownAccountStatus(s.to_owned())),
+        }
+    }
+}
+
+impl AccountStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Frozen => "frozen",
+            Self::Locked => "locked",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+/// A transaction in an account's log, along with its dispute state.
+#[derive(Clone, Copy, Debug)]
+pub struct LogEntry {
+    pub tx: Transaction,
+    pub dispute: DisputeState,
 }
 
 /// A client account.
@@ -34,21 +280,19 @@ pub struct ClientAccount {
     /// The Tx IDs are not guaranteed to be ordered, we don't know how
     /// the system generates them. But insertion order is chronological,
     /// thus the use of a IndexMap.
-    log: IndexMap<TransactionId, Transaction>,
-    /// The list of _active_ disputes.
-    ///
-    /// Understanding what disputes came and went is as easy as replaying
-    /// the transactions, and because this information is not accessed frequently,
-    /// it didn't make sense for me to store them after resolution.
     ///
-    /// **NOTE:** Because I expect the list to be short, the performance difference
-    /// of `Vec` and `HashSet` will be negligible, and for the common case,
-    /// I expect `Vec` to be ever so slightly faster.
-    disputes: Vec<TransactionId>,
+    /// Only deposits and withdrawals are logged. Dispute-related
+    /// transactions move the [`DisputeState`] of the entry they point to
+    /// instead.
+    log: IndexMap<TransactionId, LogEntry>,
+
+    /// Administrative transactions, in the order they were applied, each
+    /// numbered by its position, apart from the log.
+    audit: Vec<AdminTx>,
 
     available: Decimal,
     held: Decimal,
-    locked: bool,
+    status: AccountStatus,
 }
 
 impl ClientAccount {
@@ -57,53 +301,180 @@ impl ClientAccount {
             id,
             // Feels like more than enough for this app.
             log: IndexMap::with_capacity(100),
-            // Realistically (unless you are a merchant)
-            // how many disputes would a given client have
-            // active at any given time? Assuming 10 is enough
-            // for most cases.
-            disputes: Vec::with_capacity(10),
+            audit: vec![],
             available: Decimal::ZERO,
             held: Decimal::ZERO,
-            locked: false,
+            status: AccountStatus::Active,
         }
     }
 
     /// Appends a new transaction to the account's log and calculates
     /// the new account state.
     ///
-    /// **NOTE:** This is the only function allowed to alter the state of the log
-    /// and its immediate access values, `available`, `held` and `locked`.
-    pub fn append_tx(&mut self, tx: Transaction) -> Result<(), TransactionError> {
-        if self.locked {
-            return Err(TransactionError::LockedAccount);
+    /// Administrative transactions are rejected as `admin_only`, they
+    /// only apply through [`ClientAccount::administer`].
+    pub fn append_tx(&mut self, tx: Transaction) -> TxOutcome {
+        let Ok(outcome) = self.append_tx_with(tx, |_| Ok::<_, Infallible>(()));
+        outcome
+    }
+
+    /// Same as [`ClientAccount::append_tx`], but calls `before_apply` once the
+    /// transaction is known to apply and right before the account changes.
+    /// If it fails, the account is left untouched.
+    ///
+    /// **NOTE:** This and [`ClientAccount::administer_with`] are the only
+    /// functions allowed to alter the state of the log and its immediate
+    /// access values, `available`, `held` and `status`.
+    pub fn append_tx_with<F, E>(&mut self, tx: Transaction, before_apply: F) -> Result<TxOutcome, E>
+    where
+        F: FnOnce(&Transaction) -> Result<(), E>,
+    {
+        let diff = match TxDiff::calculate(self, &tx) {
+            Ok(diff) => diff,
+            Err(outcome) => return Ok(outcome),
+        };
+
+        let logged = tx.is_logged();
+        if logged && self.log.contains_key(&tx.id) {
+            return Ok(TxOutcome::Rejected(
+                TransactionError::DuplicateTransactionId { owner: self.id },
+            ));
         }
 
-        let diff = TxDiff::calculate(self, &tx)?;
+        before_apply(&tx)?;
 
         match diff.dispute {
-            Some(DisputeAction::Start(id)) => self.disputes.push(id),
-            Some(DisputeAction::End(id)) => self.disputes.retain(|dispute| *dispute != id),
-            None => {
-                if self.log.contains_key(&tx.id) {
-                    return Err(TransactionError::DuplicateTransactionId);
+            Some((id, state)) => {
+                if let Some(entry) = self.log.get_mut(&id) {
+                    entry.dispute = state;
                 }
-
-                let _ = self.log.insert(tx.id, tx);
             }
+            None if logged => {
+                let _ = self.log.insert(
+                    tx.id,
+                    LogEntry {
+                        tx,
+                        dispute: DisputeState::default(),
+                    },
+                );
+            }
+            None => {}
         }
 
         self.available += diff.available;
         self.held += diff.held;
 
-        if let Some(lock) = diff.lock {
-            self.locked = lock;
+        if let Some(status) = diff.status {
+            self.status = status;
         }
 
-        Ok(())
+        Ok(TxOutcome::Applied)
     }
 
-    fn in_dispute(&self, tx: &TransactionId) -> bool {
-        self.disputes.contains(tx)
+    /// Appends an administrative transaction of type `ty` ordered by an
+    /// operator to the account's audit trail, numbering it, and moves the
+    /// account to its new status.
+    pub fn administer(&mut self, ty: TransactionType, authorization: Authorization) -> TxOutcome {
+        let Ok(outcome) = self.administer_with(ty, authorization, |_| Ok::<_, Infallible>(()));
+        outcome
+    }
+
+    /// Same as [`ClientAccount::administer`], but calls `before_apply` once
+    /// the transaction is known to apply and right before the account
+    /// changes. If it fails, the account is left untouched.
+    pub fn administer_with<F, E>(
+        &mut self,
+        ty: TransactionType,
+        authorization: Authorization,
+        before_apply: F,
+    ) -> Result<TxOutcome, E>
+    where
+        F: FnOnce(&AdminTx) -> Result<(), E>,
+    {
+        let diff = match TxDiff::administer(self, ty, &authorization) {
+            Ok(diff) => diff,
+            Err(outcome) => return Ok(outcome),
+        };
+
+        let tx = AdminTx {
+            ty,
+            client_id: self.id,
+            id: self.audit.len() as u32 + 1,
+            authorization,
+        };
+        before_apply(&tx)?;
+        self.audit.push(tx);
+
+        if let Some(status) = diff.status {
+            self.status = status;
+        }
+
+        Ok(TxOutcome::Applied)
+    }
+
+    /// The dispute state of a logged transaction, if it's known to this account.
+    pub fn dispute_state(&self, tx: &TransactionId) -> Option<DisputeState> {
+        self.log.get(tx).map(|entry| entry.dispute)
+    }
+
+    /// Transactions in dispute along with what each holds, in the order
+    /// they were applied.
+    pub fn disputes(&self) -> impl Iterator<Item = (TransactionId, Decimal)> + '_ {
+        self.log
+            .iter()
+            .filter_map(|(id, entry)| match entry.dispute {
+                DisputeState::Disputed { held } => Some((*id, held)),
+                _ => None,
+            })
+    }
+
+    /// A logged transaction along with its dispute state, if it's known
+    /// to this account.
+    pub fn logged(&self, tx: &TransactionId) -> Option<&LogEntry> {
+        self.log.get(tx)
+    }
+
+    /// Rebuilds an account kept by a [`crate::storage::Storage`], along
+    /// with its audit trail and its log entries, or only the one a
+    /// transaction may need.
+    ///
+    /// Only meant for storage backends, handing back exactly what they
+    /// were given by an account earlier, and for snapshots, which check
+    /// their balances against their log first.
+    ///
+    /// **NOTE:** Backends that keep logs out of memory hand out accounts
+    /// with a partial log. This is fine, as a transaction only ever looks
+    /// at the entry sharing its ID.
+    pub(crate) fn from_storage(
+        id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        status: AccountStatus,
+        entries: impl IntoIterator<Item = LogEntry>,
+        audit: Vec<AdminTx>,
+    ) -> Self {
+        let mut account = Self::new(id);
+        account.available = available;
+        account.held = held;
+        account.status = status;
+        account.audit = audit;
+
+        for entry in entries {
+            account.log.insert(entry.tx.id, entry);
+        }
+
+        account
+    }
+
+    /// Logged transactions along with their dispute states, in the order
+    /// they were applied.
+    pub fn log(&self) -> impl Iterator<Item = &LogEntry> + '_ {
+        self.log.values()
+    }
+
+    /// Administrative transactions, in the order they were applied.
+    pub fn audit(&self) -> &[AdminTx] {
+        &self.audit
     }
 
     fn has_balance(&self, amount: Decimal) -> bool {
@@ -115,7 +486,7 @@ impl ClientAccount {
     // contains sensitive information that must not be altered regardless
     // of the ownership of the ClientAccount value.
     //
-    // The resulting values for `available`, `held` and `locked` are a result
+    // The resulting values for `available`, `held` and `status` are a result
     // of computing the log of transactions, and no code shall be allowed
     // to temper with them.
 
@@ -131,8 +502,13 @@ impl ClientAccount {
         self.held
     }
 
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// Whether the account is locked, see [`AccountStatus::Locked`].
     pub fn locked(&self) -> bool {
-        self.locked
+        self.status == AccountStatus::Locked
     }
 
     /// The total funds the client owns, a sum of `available` and `held`.
@@ -158,12 +534,13 @@ impl serde::Serialize for ClientAccount {
             format!("{dec:.4}")
         }
 
-        let mut ser = serializer.serialize_struct("ClientAccount", 5)?;
+        let mut ser = serializer.serialize_struct("ClientAccount", 6)?;
         ser.serialize_field("client", &self.id())?;
         ser.serialize_field("available", &format_decimal(self.available()))?;
         ser.serialize_field("held", &format_decimal(self.held()))?;
         ser.serialize_field("total", &format_decimal(self.total()))?;
         ser.serialize_field("locked", &self.locked())?;
+        ser.serialize_field("status", &self.status())?;
         ser.end()
     }
 }
@@ -185,75 +562,203 @@ impl serde::Serialize for ClientAccount {
 struct TxDiff {
     available: Decimal,
     held: Decimal,
-    /// Present when an account must be locked or freed.
-    lock: Option<bool>,
-    /// Present when a dispute starts or ends.
-    dispute: Option<DisputeAction>,
-}
-
-#[derive(Debug, PartialEq, Eq)]
-enum DisputeAction {
-    Start(TransactionId),
-    End(TransactionId),
+    /// Present when an account moves to a new status.
+    status: Option<AccountStatus>,
+    /// Present when a logged transaction moves to a new dispute state.
+    dispute: Option<(TransactionId, DisputeState)>,
 }
 
 impl TxDiff {
-    /// Given a transaction and the client associated to it, calculate
-    /// a state difference to be applied.
+    /// Given a transaction and the client associated to it, calculate a
+    /// state difference to be applied.
     ///
-    /// This function owns all transaction behaviors and rules.
-    fn calculate(client: &ClientAccount, tx: &Transaction) -> Result<Self, TransactionError> {
+    /// This function, along with [`TxDiff::administer`] for administrative
+    /// transactions, owns all transaction behaviors and rules.
+    fn calculate(client: &ClientAccount, tx: &Transaction) -> Result<Self, TxOutcome> {
+        Self::allowed(client.status, tx.ty)?;
+
         match tx.ty {
             TransactionType::Deposit { amount } => {
                 if amount.is_sign_negative() {
-                    return Err(TransactionError::AmountCannotBeNegative);
+                    return Err(TransactionError::AmountCannotBeNegative.into());
                 }
 
-                return Ok(Self::deposit(amount));
+                Ok(Self::deposit(amount))
             }
 
             TransactionType::Withdrawal { amount } => {
                 if amount.is_sign_negative() {
-                    return Err(TransactionError::AmountCannotBeNegative);
+                    return Err(TransactionError::AmountCannotBeNegative.into());
                 }
 
                 if !client.has_balance(amount) {
-                    return Err(TransactionError::NotEnoughBalance);
+                    return Err(TransactionError::NotEnoughBalance.into());
                 }
 
-                return Ok(Self::withdraw(amount));
+                Ok(Self::withdraw(amount))
             }
 
-            TransactionType::Dispute => {
-                if let Some(target) = client.log.get(&tx.id)
-                    && let Some(amount) = target.deposit_amount()
-                    && !client.in_dispute(&tx.id)
-                {
-                    return Ok(Self::dispute(tx.id, amount));
+            TransactionType::Dispute { amount } => {
+                let target = Self::dispute_target(client, tx)?;
+                let held = target.dispute.held();
+
+                match target.tx.ty {
+                    TransactionType::Deposit { amount: original } => {
+                        let amount = Self::disputable(original, held, amount)?;
+                        Ok(Self::dispute(tx.id, held, amount))
+                    }
+                    TransactionType::Withdrawal { amount: original } => {
+                        let amount = Self::disputable(original, held, amount)?;
+                        Ok(Self::dispute_withdrawal(tx.id, held, amount))
+                    }
+                    _ => Err(IgnoreReason::NotDisputable.into()),
                 }
             }
 
             TransactionType::Resolve => {
-                if let Some(target) = client.log.get(&tx.id)
-                    && let Some(amount) = target.deposit_amount()
-                    && client.in_dispute(&tx.id)
-                {
-                    return Ok(Self::resolve(tx.id, amount));
+                let target = Self::dispute_target(client, tx)?;
+                let held = Self::held_in_dispute(target)?;
+
+                match target.tx.ty {
+                    TransactionType::Deposit { .. } => Ok(Self::resolve(tx.id, held)),
+                    TransactionType::Withdrawal { .. } => Ok(Self::resolve_withdrawal(tx.id, held)),
+                    _ => Err(IgnoreReason::NotDisputable.into()),
                 }
             }
 
             TransactionType::Chargeback => {
-                if let Some(target) = client.log.get(&tx.id)
-                    && let Some(amount) = target.deposit_amount()
-                    && client.in_dispute(&tx.id)
-                {
-                    return Ok(Self::chargeback(tx.id, amount));
+                let target = Self::dispute_target(client, tx)?;
+                let held = Self::held_in_dispute(target)?;
+
+                match target.tx.ty {
+                    TransactionType::Deposit { .. } => Ok(Self::chargeback(tx.id, held)),
+                    TransactionType::Withdrawal { .. } => {
+                        Ok(Self::chargeback_withdrawal(tx.id, held))
+                    }
+                    _ => Err(IgnoreReason::NotDisputable.into()),
                 }
             }
+
+            TransactionType::Freeze
+            | TransactionType::Unfreeze
+            | TransactionType::Unlock
+            | TransactionType::Close => Err(TransactionError::AdminOnly.into()),
+        }
+    }
+
+    /// Given an administrative transaction, who ordered it, and the client
+    /// associated to it, calculate a state difference to be applied.
+    fn administer(
+        client: &ClientAccount,
+        ty: TransactionType,
+        authorization: &Authorization,
+    ) -> Result<Self, TxOutcome> {
+        Self::allowed(client.status, ty)?;
+
+        if authorization.operator.trim().is_empty() {
+            return Err(TransactionError::MissingOperator.into());
+        }
+
+        let status = match (ty, client.status) {
+            (TransactionType::Freeze, AccountStatus::Active) => AccountStatus::Frozen,
+            (TransactionType::Freeze, _) => return Err(IgnoreReason::NotActive.into()),
+            (TransactionType::Unfreeze, AccountStatus::Frozen) => AccountStatus::Active,
+            (TransactionType::Unfreeze, _) => return Err(IgnoreReason::NotFrozen.into()),
+            (TransactionType::Unlock, AccountStatus::Locked) => AccountStatus::Active,
+            (TransactionType::Unlock, _) => return Err(IgnoreReason::NotLocked.into()),
+            (TransactionType::Close, _)
+                if !client.available.is_zero() || !client.held.is_zero() =>
+            {
+                return Err(TransactionError::BalanceNotZero.into());
+            }
+            (TransactionType::Close, _) => AccountStatus::Closed,
+            // Anything else is never ordered by an operator.
+            _ => return Err(TransactionError::AdminOnly.into()),
+        };
+
+        Ok(Self::move_to(status))
+    }
+
+    /// Whether accounts with `status` take transactions of type `ty`, and
+    /// the error they are rejected with otherwise.
+    ///
+    /// **NOTE:** Only the types that are never allowed are rejected here.
+    /// Administrative transactions that don't fit the current status, such
+    /// as unlocking an account that isn't locked, are ignored later on.
+    fn allowed(status: AccountStatus, ty: TransactionType) -> Result<(), TransactionError> {
+        let allowed = match status {
+            AccountStatus::Active => true,
+            AccountStatus::Frozen => !matches!(ty, TransactionType::Withdrawal { .. }),
+            AccountStatus::Locked => {
+                matches!(ty, TransactionType::Unlock | TransactionType::Close)
+            }
+            AccountStatus::Closed => false,
+        };
+
+        match (allowed, status) {
+            (true, _) => Ok(()),
+            (false, AccountStatus::Closed) => Err(TransactionError::ClosedAccount),
+            (false, AccountStatus::Locked) => Err(TransactionError::LockedAccount),
+            (false, _) => Err(TransactionError::FrozenAccount),
+        }
+    }
+
+    /// Finds the logged transaction a dispute, resolution or chargeback
+    /// refers to. Chargebacks are final, nothing can refer to them anymore.
+    fn dispute_target<'a>(
+        client: &'a ClientAccount,
+        tx: &Transaction,
+    ) -> Result<&'a LogEntry, TxOutcome> {
+        let target = client.log.get(&tx.id).ok_or(IgnoreReason::UnknownTarget)?;
+
+        if let DisputeState::ChargedBack { .. } = target.dispute {
+            return Err(TransactionError::AlreadyChargedBack.into());
+        }
+
+        Ok(target)
+    }
+
+    /// How much an open dispute of `target` holds, for it to be resolved
+    /// or charged back.
+    ///
+    /// Ending a dispute that never started is just ignored, partners send
+    /// those. Ending one that already ended is a transition we don't allow.
+    fn held_in_dispute(target: &LogEntry) -> Result<Decimal, TxOutcome> {
+        match target.dispute {
+            DisputeState::Disputed { held } => Ok(held),
+            DisputeState::Resolved => Err(TransactionError::AlreadyResolved.into()),
+            _ => Err(IgnoreReason::NotInDispute.into()),
+        }
+    }
+
+    /// How much of the `original` amount a dispute holds.
+    ///
+    /// Disputes may hold only part of the original amount, and can be
+    /// raised more than once, as long as the sum of what is held does not
+    /// exceed the original amount.
+    fn disputable(
+        original: Decimal,
+        held: Decimal,
+        requested: Option<Decimal>,
+    ) -> Result<Decimal, TxOutcome> {
+        let remaining = original - held;
+        if remaining.is_zero() {
+            return Err(IgnoreReason::AlreadyDisputed.into());
         }
 
-        // In any other case, we ignore it.
-        Ok(Default::default())
+        match requested {
+            Some(amount) if amount.is_sign_negative() => {
+                Err(TransactionError::AmountCannotBeNegative.into())
+            }
+            // **NOTE:** A dispute holding nothing would be logged as if it
+            // had been raised, so it's refused rather than applied as a no-op.
+            Some(amount) if amount.is_zero() => Err(TransactionError::InvalidDisputeAmount.into()),
+            Some(amount) if amount > remaining => {
+                Err(TransactionError::DisputeExceedsAmount.into())
+            }
+            Some(amount) => Ok(amount),
+            None => Ok(remaining),
+        }
     }
 
     /// Increases available balance.
@@ -272,12 +777,18 @@ impl TxDiff {
         }
     }
 
-    /// Holds the disputed amount, decreasing available balance.
-    fn dispute(tx: TransactionId, amount: Decimal) -> TxDiff {
+    /// Holds the disputed amount, on top of what is `already_held`,
+    /// decreasing available balance.
+    fn dispute(tx: TransactionId, already_held: Decimal, amount: Decimal) -> TxDiff {
         Self {
             available: amount.neg(),
             held: amount,
-            dispute: Some(DisputeAction::Start(tx)),
+            dispute: Some((
+                tx,
+                DisputeState::Disputed {
+                    held: already_held + amount,
+                },
+            )),
             ..Default::default()
         }
     }
@@ -287,7 +798,7 @@ impl TxDiff {
         Self {
             available: amount,
             held: amount.neg(),
-            dispute: Some(DisputeAction::End(tx)),
+            dispute: Some((tx, DisputeState::Resolved)),
             ..Default::default()
         }
     }
@@ -296,8 +807,57 @@ impl TxDiff {
     fn chargeback(tx: TransactionId, amount: Decimal) -> TxDiff {
         Self {
             held: amount.neg(),
-            lock: Some(true),
-            dispute: Some(DisputeAction::End(tx)),
+            status: Some(AccountStatus::Locked),
+            dispute: Some((tx, DisputeState::ChargedBack { amount })),
+            ..Default::default()
+        }
+    }
+
+    /// Credits a withdrawn amount back as held balance. Available
+    /// balance is left untouched, as those funds already left the account.
+    ///
+    /// **NOTE:** This is how failed payouts show up. The money might be
+    /// coming back, but until the dispute ends, the client can't use it.
+    fn dispute_withdrawal(tx: TransactionId, already_held: Decimal, amount: Decimal) -> TxDiff {
+        Self {
+            held: amount,
+            dispute: Some((
+                tx,
+                DisputeState::Disputed {
+                    held: already_held + amount,
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    /// Drops the held amount, the original withdrawal stands.
+    fn resolve_withdrawal(tx: TransactionId, amount: Decimal) -> TxDiff {
+        Self {
+            held: amount.neg(),
+            dispute: Some((tx, DisputeState::Resolved)),
+            ..Default::default()
+        }
+    }
+
+    /// Reverses the withdrawal, moving the held amount back to available
+    /// balance.
+    ///
+    /// **NOTE:** Unlike deposit chargebacks, the account is left as it is.
+    /// The client got their money back, they didn't take any.
+    fn chargeback_withdrawal(tx: TransactionId, amount: Decimal) -> TxDiff {
+        Self {
+            available: amount,
+            held: amount.neg(),
+            dispute: Some((tx, DisputeState::ChargedBack { amount })),
+            ..Default::default()
+        }
+    }
+
+    /// Moves an account to a new status, balances are left untouched.
+    fn move_to(status: AccountStatus) -> TxDiff {
+        Self {
+            status: Some(status),
             ..Default::default()
         }
     }
@@ -320,9 +880,11 @@ mod tests {
     fn client(tys: &[TransactionType]) -> ClientAccount {
         let mut client = ClientAccount::new(ClientId::new(0));
         for ty in tys {
-            client
-                .append_tx(tx(&client, *ty))
-                .expect("valid transactions");
+            assert_eq!(
+                client.append_tx(tx(&client, *ty)),
+                TxOutcome::Applied,
+                "valid transactions"
+            );
         }
         client
     }
@@ -336,12 +898,18 @@ mod tests {
     }
 
     fn deposit(client: &mut ClientAccount, amount: Decimal) -> TransactionId {
-        client
-            .append_tx(tx(client, TransactionType::Deposit { amount }))
-            .expect("deposit must never fail unless account is locked");
+        assert_eq!(
+            client.append_tx(tx(client, TransactionType::Deposit { amount })),
+            TxOutcome::Applied,
+            "deposit must never fail unless account is locked"
+        );
         *client.log.last().unwrap().0
     }
 
+    fn hold(client: &mut ClientAccount, id: TransactionId, held: Decimal) {
+        client.log.get_mut(&id).unwrap().dispute = DisputeState::Disputed { held };
+    }
+
     #[test]
     fn deposit_diff_only_alters_available() {
         let client = client(&[]);
@@ -367,7 +935,7 @@ mod tests {
 
         let err = TxDiff::calculate(&client, &tx)
             .expect_err("withdrawal fails if not enough balance is available");
-        assert_eq!(err, TransactionError::NotEnoughBalance);
+        assert_eq!(err, TransactionError::NotEnoughBalance.into());
 
         deposit(&mut client, amount);
 
@@ -383,7 +951,7 @@ mod tests {
     }
 
     const DISPUTE_RELATED_VARIANTS: [TransactionType; 3] = [
-        TransactionType::Dispute,
+        TransactionType::Dispute { amount: None },
         TransactionType::Resolve,
         TransactionType::Chargeback,
     ];
@@ -394,25 +962,39 @@ mod tests {
 
         for ty in DISPUTE_RELATED_VARIANTS {
             let dispute = tx(&client, ty);
-            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
-            assert_eq!(diff, TxDiff::default(), "{ty:?} refers to unknown tx");
+            let err = TxDiff::calculate(&client, &dispute).expect_err("dispute is ignored");
+            assert_eq!(
+                err,
+                IgnoreReason::UnknownTarget.into(),
+                "{ty:?} refers to unknown tx"
+            );
         }
     }
 
     #[test]
-    fn dispute_related_is_ignored_for_unsupported_tx() {
+    fn dispute_related_fails_for_charged_back_tx() {
         let amount = dec!(10.0);
-        let client = client(&[
-            TransactionType::Deposit { amount },
-            TransactionType::Withdrawal { amount },
-        ]);
+        let mut client = client(&[]);
+        let deposit_id = deposit(&mut client, amount);
+        client.log.get_mut(&deposit_id).unwrap().dispute = DisputeState::ChargedBack { amount };
 
         for ty in DISPUTE_RELATED_VARIANTS {
             let mut dispute = tx(&client, ty);
-            dispute.id = *client.log.last().unwrap().0;
+            dispute.id = deposit_id;
 
-            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
-            assert_eq!(diff, TxDiff::default(), "{ty:?} refers to unsupported tx");
+            let err = TxDiff::calculate(&client, &dispute).expect_err("chargebacks are final");
+            assert_eq!(err, TransactionError::AlreadyChargedBack.into(), "{ty:?}");
+        }
+    }
+
+    #[test]
+    fn dispute_related_is_never_logged() {
+        let mut client = client(&[]);
+
+        for ty in DISPUTE_RELATED_VARIANTS {
+            let dispute = tx(&client, ty);
+            let _ = client.append_tx(dispute);
+            assert!(client.log.is_empty(), "{ty:?} was logged");
         }
     }
 
@@ -425,37 +1007,175 @@ mod tests {
             let mut client = client(&[]);
 
             let deposit_id = deposit(&mut client, amount);
-            client.disputes.push(deposit_id);
+            hold(&mut client, deposit_id, amount);
 
-            let mut dispute = tx(&client, TransactionType::Dispute);
+            let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
             dispute.id = deposit_id;
 
-            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
+            let err = TxDiff::calculate(&client, &dispute).expect_err("dispute is ignored");
             assert_eq!(
-                diff,
-                TxDiff::default(),
+                err,
+                IgnoreReason::AlreadyDisputed.into(),
                 "dispute refers to already disputed tx"
             );
         }
 
+        #[test]
+        fn never_reaches_administrative_txs() {
+            let mut client = client(&[]);
+            client.status = AccountStatus::Locked;
+            let outcome = client.administer(TransactionType::Unlock, authorized("risk-7"));
+            assert!(outcome.is_applied());
+
+            let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
+            dispute.id = TransactionId::new(client.audit()[0].id);
+
+            let err = TxDiff::calculate(&client, &dispute).expect_err("dispute is ignored");
+            assert_eq!(
+                err,
+                IgnoreReason::UnknownTarget.into(),
+                "administrative txs are not in the log"
+            );
+        }
+
         #[test]
         fn holds_disputed_balance() {
             let amount = dec!(10.0);
             let client = client(&[TransactionType::Deposit { amount }]);
 
-            let mut dispute = tx(&client, TransactionType::Dispute);
+            let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
             dispute.id = *client.log.last().unwrap().0;
 
             let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
             let expected = TxDiff {
                 available: amount.neg(),
                 held: amount,
-                dispute: Some(DisputeAction::Start(dispute.id)),
+                dispute: Some((dispute.id, DisputeState::Disputed { held: amount })),
                 ..Default::default()
             };
 
             assert_eq!(diff, expected, "dispute not holding balance");
         }
+
+        #[test]
+        fn holds_resolved_txs_again() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+            let deposit_id = deposit(&mut client, amount);
+            client.log.get_mut(&deposit_id).unwrap().dispute = DisputeState::Resolved;
+
+            let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
+            dispute.id = deposit_id;
+
+            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
+            let expected = TxDiff {
+                available: amount.neg(),
+                held: amount,
+                dispute: Some((dispute.id, DisputeState::Disputed { held: amount })),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "resolved tx not disputable again");
+        }
+
+        #[test]
+        fn holds_disputed_withdrawal() {
+            let amount = dec!(10.0);
+            let client = client(&[
+                TransactionType::Deposit { amount },
+                TransactionType::Withdrawal { amount },
+            ]);
+
+            let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
+            dispute.id = *client.log.last().unwrap().0;
+
+            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
+            let expected = TxDiff {
+                held: amount,
+                dispute: Some((dispute.id, DisputeState::Disputed { held: amount })),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "dispute not holding withdrawn balance");
+        }
+
+        #[test]
+        fn holds_only_the_disputed_portion() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+            let deposit_id = deposit(&mut client, amount);
+
+            let mut dispute = tx(
+                &client,
+                TransactionType::Dispute {
+                    amount: Some(dec!(4.0)),
+                },
+            );
+            dispute.id = deposit_id;
+
+            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
+            let expected = TxDiff {
+                available: dec!(-4.0),
+                held: dec!(4.0),
+                dispute: Some((dispute.id, DisputeState::Disputed { held: dec!(4.0) })),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "dispute not holding partial balance");
+        }
+
+        #[test]
+        fn holds_what_is_left_of_a_partially_disputed_tx() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+            let deposit_id = deposit(&mut client, amount);
+            hold(&mut client, deposit_id, dec!(4.0));
+
+            let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
+            dispute.id = deposit_id;
+
+            let diff = TxDiff::calculate(&client, &dispute).expect("dispute is valid");
+            let expected = TxDiff {
+                available: dec!(-6.0),
+                held: dec!(6.0),
+                dispute: Some((dispute.id, DisputeState::Disputed { held: amount })),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "dispute not holding remaining balance");
+        }
+
+        #[test]
+        fn fails_when_exceeding_the_undisputed_amount() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+            let deposit_id = deposit(&mut client, amount);
+            hold(&mut client, deposit_id, dec!(4.0));
+
+            let mut dispute = tx(
+                &client,
+                TransactionType::Dispute {
+                    amount: Some(dec!(6.5)),
+                },
+            );
+            dispute.id = deposit_id;
+
+            let err = TxDiff::calculate(&client, &dispute)
+                .expect_err("dispute exceeds the undisputed amount");
+            assert_eq!(err, TransactionError::DisputeExceedsAmount.into());
+
+            dispute.ty = TransactionType::Dispute {
+                amount: Some(dec!(-1.0)),
+            };
+            let err = TxDiff::calculate(&client, &dispute).expect_err("dispute is negative");
+            assert_eq!(err, TransactionError::AmountCannotBeNegative.into());
+
+            dispute.ty = TransactionType::Dispute {
+                amount: Some(Decimal::ZERO),
+            };
+            let err = TxDiff::calculate(&client, &dispute).expect_err("dispute is empty");
+            assert_eq!(err, TransactionError::InvalidDisputeAmount.into());
+        }
     }
 
     mod resolve {
@@ -470,8 +1190,12 @@ mod tests {
             let mut resolve = tx(&client, TransactionType::Resolve);
             resolve.id = deposit_id;
 
-            let diff = TxDiff::calculate(&client, &resolve).expect("resolve is valid");
-            assert_eq!(diff, TxDiff::default(), "resolve refers to undisputed tx");
+            let err = TxDiff::calculate(&client, &resolve).expect_err("resolve is ignored");
+            assert_eq!(
+                err,
+                IgnoreReason::NotInDispute.into(),
+                "resolve refers to undisputed tx"
+            );
         }
 
         #[test]
@@ -480,7 +1204,7 @@ mod tests {
             let mut client = client(&[]);
 
             let deposit_id = deposit(&mut client, amount);
-            client.disputes.push(deposit_id);
+            hold(&mut client, deposit_id, amount);
 
             let mut resolve = tx(&client, TransactionType::Resolve);
             resolve.id = deposit_id;
@@ -489,12 +1213,74 @@ mod tests {
             let expected = TxDiff {
                 available: amount,
                 held: amount.neg(),
-                dispute: Some(DisputeAction::End(resolve.id)),
+                dispute: Some((resolve.id, DisputeState::Resolved)),
                 ..Default::default()
             };
 
             assert_eq!(diff, expected, "resolve not freeing balance");
         }
+
+        #[test]
+        fn drops_disputed_withdrawal() {
+            let amount = dec!(10.0);
+            let mut client = client(&[
+                TransactionType::Deposit { amount },
+                TransactionType::Withdrawal { amount },
+            ]);
+
+            let withdrawal_id = *client.log.last().unwrap().0;
+            hold(&mut client, withdrawal_id, amount);
+
+            let mut resolve = tx(&client, TransactionType::Resolve);
+            resolve.id = withdrawal_id;
+
+            let diff = TxDiff::calculate(&client, &resolve).expect("resolve is valid");
+            let expected = TxDiff {
+                held: amount.neg(),
+                dispute: Some((resolve.id, DisputeState::Resolved)),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "resolve not dropping withdrawn balance");
+        }
+
+        #[test]
+        fn frees_only_the_disputed_portion() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+
+            let deposit_id = deposit(&mut client, amount);
+            hold(&mut client, deposit_id, dec!(4.0));
+
+            let mut resolve = tx(&client, TransactionType::Resolve);
+            resolve.id = deposit_id;
+
+            let diff = TxDiff::calculate(&client, &resolve).expect("resolve is valid");
+            let expected = TxDiff {
+                available: dec!(4.0),
+                held: dec!(-4.0),
+                dispute: Some((resolve.id, DisputeState::Resolved)),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "resolve not freeing partial balance");
+        }
+
+        #[test]
+        fn fails_for_resolved_txs() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+            let deposit_id = deposit(&mut client, amount);
+            client.log.get_mut(&deposit_id).unwrap().dispute = DisputeState::Resolved;
+
+            for ty in [TransactionType::Resolve, TransactionType::Chargeback] {
+                let mut end = tx(&client, ty);
+                end.id = deposit_id;
+
+                let err = TxDiff::calculate(&client, &end).expect_err("dispute already ended");
+                assert_eq!(err, TransactionError::AlreadyResolved.into(), "{ty:?}");
+            }
+        }
     }
 
     mod chargeback {
@@ -509,10 +1295,10 @@ mod tests {
             let mut chargeback = tx(&client, TransactionType::Chargeback);
             chargeback.id = deposit_id;
 
-            let diff = TxDiff::calculate(&client, &chargeback).expect("chargeback is valid");
+            let err = TxDiff::calculate(&client, &chargeback).expect_err("chargeback is ignored");
             assert_eq!(
-                diff,
-                TxDiff::default(),
+                err,
+                IgnoreReason::NotInDispute.into(),
                 "chargeback refers to undisputed tx"
             );
         }
@@ -523,7 +1309,7 @@ mod tests {
             let mut client = client(&[]);
 
             let deposit_id = deposit(&mut client, amount);
-            client.disputes.push(deposit_id);
+            hold(&mut client, deposit_id, amount);
 
             let mut chargeback = tx(&client, TransactionType::Chargeback);
             chargeback.id = deposit_id;
@@ -531,24 +1317,183 @@ mod tests {
             let diff = TxDiff::calculate(&client, &chargeback).expect("chargeback is valid");
             let expected = TxDiff {
                 held: amount.neg(),
-                lock: Some(true),
-                dispute: Some(DisputeAction::End(chargeback.id)),
+                status: Some(AccountStatus::Locked),
+                dispute: Some((chargeback.id, DisputeState::ChargedBack { amount })),
                 ..Default::default()
             };
 
             assert_eq!(diff, expected, "resolve not burning balance");
         }
+
+        #[test]
+        fn reverses_disputed_withdrawal() {
+            let amount = dec!(10.0);
+            let mut client = client(&[
+                TransactionType::Deposit { amount },
+                TransactionType::Withdrawal { amount },
+            ]);
+
+            let withdrawal_id = *client.log.last().unwrap().0;
+            hold(&mut client, withdrawal_id, amount);
+
+            let mut chargeback = tx(&client, TransactionType::Chargeback);
+            chargeback.id = withdrawal_id;
+
+            let diff = TxDiff::calculate(&client, &chargeback).expect("chargeback is valid");
+            let expected = TxDiff {
+                available: amount,
+                held: amount.neg(),
+                dispute: Some((chargeback.id, DisputeState::ChargedBack { amount })),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "chargeback not reversing withdrawal");
+        }
+
+        #[test]
+        fn burns_only_the_disputed_portion() {
+            let amount = dec!(10.0);
+            let mut client = client(&[]);
+
+            let deposit_id = deposit(&mut client, amount);
+            hold(&mut client, deposit_id, dec!(4.0));
+
+            let mut chargeback = tx(&client, TransactionType::Chargeback);
+            chargeback.id = deposit_id;
+
+            let diff = TxDiff::calculate(&client, &chargeback).expect("chargeback is valid");
+            let expected = TxDiff {
+                held: dec!(-4.0),
+                status: Some(AccountStatus::Locked),
+                dispute: Some((
+                    chargeback.id,
+                    DisputeState::ChargedBack { amount: dec!(4.0) },
+                )),
+                ..Default::default()
+            };
+
+            assert_eq!(diff, expected, "chargeback not burning partial balance");
+        }
+    }
+
+    #[test]
+    fn calculate_follows_the_account_status() {
+        let mut client = client(&[TransactionType::Deposit { amount: dec!(10) }]);
+        client.status = AccountStatus::Frozen;
+
+        let withdrawal = tx(&client, TransactionType::Withdrawal { amount: dec!(1) });
+        let err = TxDiff::calculate(&client, &withdrawal).expect_err("account is frozen");
+        assert_eq!(err, TxOutcome::Rejected(TransactionError::FrozenAccount));
+
+        let deposit = tx(&client, TransactionType::Deposit { amount: dec!(1) });
+        let diff = TxDiff::calculate(&client, &deposit).expect("deposits still apply");
+        assert_eq!(diff, TxDiff::deposit(dec!(1)));
     }
 
     #[test]
     fn append_fails_for_locked_accounts() {
         let mut client = client(&[]);
-        client.locked = true;
+        client.status = AccountStatus::Locked;
+
+        let outcome = client.append_tx(tx(&client, TransactionType::Deposit { amount: dec!(10) }));
+        assert_eq!(
+            outcome,
+            TxOutcome::Rejected(TransactionError::LockedAccount),
+            "account is locked"
+        );
+    }
+
+    /// Who ordered an administrative transaction.
+    fn authorized(operator: &str) -> Authorization {
+        Authorization {
+            operator: operator.to_owned(),
+            reason: "reviewed by risk".to_owned(),
+        }
+    }
+
+    #[test]
+    fn unlock_reinstates_locked_accounts() {
+        let mut client = client(&[TransactionType::Deposit { amount: dec!(10) }]);
+
+        let outcome = client.administer(TransactionType::Unlock, authorized("risk-7"));
+        assert_eq!(outcome, TxOutcome::Ignored(IgnoreReason::NotLocked));
+
+        client.status = AccountStatus::Locked;
+        let outcome = client.administer(TransactionType::Unlock, authorized(" "));
+        assert_eq!(
+            outcome,
+            TxOutcome::Rejected(TransactionError::MissingOperator)
+        );
+        let outcome = client.append_tx(tx(&client, TransactionType::Unlock));
+        assert_eq!(outcome, TxOutcome::Rejected(TransactionError::AdminOnly));
+        assert!(client.locked());
+        assert!(client.audit().is_empty());
+
+        let outcome = client.administer(TransactionType::Unlock, authorized("risk-7"));
+        assert!(outcome.is_applied());
+        assert_eq!(client.status(), AccountStatus::Active);
+        assert_eq!(client.available, dec!(10));
+        assert_eq!(client.log.len(), 1, "administrative txs are not logged");
+        assert!(matches!(
+            client.audit(),
+            [AdminTx { id: 1, ty: TransactionType::Unlock, authorization, .. }]
+                if authorization.operator == "risk-7"
+        ));
+
+        assert!(
+            client
+                .append_tx(tx(&client, TransactionType::Deposit { amount: dec!(1) }))
+                .is_applied()
+        );
+    }
 
-        let err = client
-            .append_tx(tx(&client, TransactionType::Deposit { amount: dec!(10) }))
-            .expect_err("account is locked");
-        assert_eq!(err, TransactionError::LockedAccount);
+    #[test]
+    fn statuses_allow_their_own_transactions() {
+        let mut client = client(&[TransactionType::Deposit { amount: dec!(10) }]);
+
+        let freeze = client.administer(TransactionType::Freeze, authorized("risk-7"));
+        assert!(freeze.is_applied());
+        assert_eq!(client.status(), AccountStatus::Frozen);
+        assert_eq!(
+            client.administer(TransactionType::Freeze, authorized("risk-7")),
+            TxOutcome::Ignored(IgnoreReason::NotActive)
+        );
+
+        let withdrawal = TransactionType::Withdrawal { amount: dec!(10) };
+        assert_eq!(
+            client.append_tx(tx(&client, withdrawal)),
+            TxOutcome::Rejected(TransactionError::FrozenAccount)
+        );
+        assert!(
+            client
+                .append_tx(tx(&client, TransactionType::Deposit { amount: dec!(1) }))
+                .is_applied()
+        );
+
+        assert_eq!(
+            client.administer(TransactionType::Close, authorized("risk-7")),
+            TxOutcome::Rejected(TransactionError::BalanceNotZero)
+        );
+
+        let unfreeze = client.administer(TransactionType::Unfreeze, authorized("risk-7"));
+        assert!(unfreeze.is_applied());
+        let withdrawal = TransactionType::Withdrawal { amount: dec!(11) };
+        assert!(client.append_tx(tx(&client, withdrawal)).is_applied());
+
+        let close = client.administer(TransactionType::Close, authorized("risk-7"));
+        assert!(close.is_applied());
+        assert_eq!(client.status(), AccountStatus::Closed);
+        assert_eq!(
+            client.append_tx(tx(&client, TransactionType::Deposit { amount: dec!(1) })),
+            TxOutcome::Rejected(TransactionError::ClosedAccount)
+        );
+        assert_eq!(
+            client.administer(TransactionType::Close, authorized("risk-7")),
+            TxOutcome::Rejected(TransactionError::ClosedAccount)
+        );
+
+        let ids: Vec<_> = client.audit().iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, [1, 2, 3], "only applied ones are numbered");
     }
 
     #[test]
@@ -559,60 +1504,148 @@ mod tests {
         let mut tx = tx(&client, TransactionType::Deposit { amount: dec!(10) });
         tx.id = deposit_id;
 
-        let err = client.append_tx(tx).expect_err("tx id is a duplicate");
-        assert_eq!(err, TransactionError::DuplicateTransactionId);
+        let outcome = client.append_tx(tx);
+        assert_eq!(
+            outcome,
+            TxOutcome::Rejected(TransactionError::DuplicateTransactionId { owner: client.id }),
+            "tx id is a duplicate"
+        );
+    }
+
+    #[test]
+    fn append_disputes_resolved_txs_again() {
+        let mut client = client(&[]);
+        let deposit_id = deposit(&mut client, dec!(10));
+
+        for ty in [
+            TransactionType::Dispute { amount: None },
+            TransactionType::Resolve,
+            TransactionType::Dispute { amount: None },
+        ] {
+            let mut tx = tx(&client, ty);
+            tx.id = deposit_id;
+            assert!(client.append_tx(tx).is_applied(), "{ty:?}");
+        }
+
+        assert!(client.available.is_zero());
+        assert_eq!(client.held, dec!(10));
+        assert_eq!(
+            client.dispute_state(&deposit_id),
+            Some(DisputeState::Disputed { held: dec!(10) })
+        );
     }
 
     #[test]
     fn append_updated_client_state_after_multiple_disputes() {
         let mut client = client(&[]);
 
-        client
-            .append_tx(tx(&client, TransactionType::Deposit { amount: dec!(10) }))
-            .unwrap();
+        assert!(
+            client
+                .append_tx(tx(&client, TransactionType::Deposit { amount: dec!(10) }))
+                .is_applied()
+        );
         assert_eq!(client.available, dec!(10));
         assert!(client.held.is_zero());
         assert_eq!(client.total(), dec!(10));
-        assert!(!client.locked);
+        assert!(!client.locked());
         assert_eq!(client.log.len(), 1);
 
-        client
-            .append_tx(tx(&client, TransactionType::Withdrawal { amount: dec!(4) }))
-            .unwrap();
+        assert!(
+            client
+                .append_tx(tx(&client, TransactionType::Withdrawal { amount: dec!(4) }))
+                .is_applied()
+        );
         assert_eq!(client.available, dec!(6));
         assert!(client.held.is_zero());
         assert_eq!(client.total(), dec!(6));
-        assert!(!client.locked);
+        assert!(!client.locked());
         assert_eq!(client.log.len(), 2);
 
-        let mut dispute = tx(&client, TransactionType::Dispute);
+        let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
         dispute.id = *client.log.first().unwrap().0;
-        client.append_tx(dispute).unwrap();
+        assert!(client.append_tx(dispute).is_applied());
         assert_eq!(client.available, dec!(-4));
         assert_eq!(client.held, dec!(10));
         assert_eq!(client.total(), dec!(6));
-        assert!(!client.locked);
-        assert_eq!(client.disputes, [dispute.id]);
+        assert!(!client.locked());
+        assert_eq!(
+            client.dispute_state(&dispute.id),
+            Some(DisputeState::Disputed { held: dec!(10) })
+        );
 
         let mut resolve = tx(&client, TransactionType::Resolve);
         resolve.id = *client.log.first().unwrap().0;
-        client.append_tx(resolve).unwrap();
+        assert!(client.append_tx(resolve).is_applied());
         assert_eq!(client.available, dec!(6));
         assert!(client.held.is_zero());
         assert_eq!(client.total(), dec!(6));
-        assert!(!client.locked);
-        assert!(client.disputes.is_empty());
+        assert!(!client.locked());
+        assert_eq!(
+            client.dispute_state(&resolve.id),
+            Some(DisputeState::Resolved)
+        );
 
-        let mut dispute = tx(&client, TransactionType::Dispute);
+        let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
         dispute.id = *client.log.first().unwrap().0;
-        client.append_tx(dispute).unwrap();
+        assert!(client.append_tx(dispute).is_applied());
         let mut chargeback = tx(&client, TransactionType::Chargeback);
         chargeback.id = *client.log.first().unwrap().0;
-        client.append_tx(chargeback).unwrap();
+        assert!(client.append_tx(chargeback).is_applied());
         assert_eq!(client.available, dec!(-4));
         assert!(client.held.is_zero());
         assert_eq!(client.total(), dec!(-4));
-        assert!(client.locked);
-        assert!(client.disputes.is_empty());
+        assert!(client.locked());
+        assert_eq!(
+            client.dispute_state(&chargeback.id),
+            Some(DisputeState::ChargedBack { amount: dec!(10) })
+        );
+    }
+
+    #[test]
+    fn append_updated_client_state_after_withdrawal_disputes() {
+        let mut client = client(&[
+            TransactionType::Deposit { amount: dec!(10) },
+            TransactionType::Withdrawal { amount: dec!(4) },
+        ]);
+        let withdrawal_id = *client.log.last().unwrap().0;
+
+        let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
+        dispute.id = withdrawal_id;
+        assert!(client.append_tx(dispute).is_applied());
+        assert_eq!(client.available, dec!(6));
+        assert_eq!(client.held, dec!(4));
+        assert_eq!(client.total(), dec!(10));
+        assert!(!client.locked());
+        assert_eq!(
+            client.dispute_state(&withdrawal_id),
+            Some(DisputeState::Disputed { held: dec!(4) })
+        );
+
+        let mut resolve = tx(&client, TransactionType::Resolve);
+        resolve.id = withdrawal_id;
+        assert!(client.append_tx(resolve).is_applied());
+        assert_eq!(client.available, dec!(6));
+        assert!(client.held.is_zero());
+        assert_eq!(client.total(), dec!(6));
+        assert!(!client.locked());
+        assert_eq!(
+            client.dispute_state(&withdrawal_id),
+            Some(DisputeState::Resolved)
+        );
+
+        let mut dispute = tx(&client, TransactionType::Dispute { amount: None });
+        dispute.id = withdrawal_id;
+        assert!(client.append_tx(dispute).is_applied());
+        let mut chargeback = tx(&client, TransactionType::Chargeback);
+        chargeback.id = withdrawal_id;
+        assert!(client.append_tx(chargeback).is_applied());
+        assert_eq!(client.available, dec!(10));
+        assert!(client.held.is_zero());
+        assert_eq!(client.total(), dec!(10));
+        assert!(!client.locked());
+        assert_eq!(
+            client.dispute_state(&withdrawal_id),
+            Some(DisputeState::ChargedBack { amount: dec!(4) })
+        );
     }
 }
@@ -0,0 +1,234 @@
+use std::{fs, io, path::Path};
+
+use rusqlite::{Connection, params};
+
+use crate::{ClientBook, client::DisputeState, storage::Storage, transaction::TransactionType};
+
+const SCHEMA: &str = "
+    CREATE TABLE accounts (
+        client    INTEGER PRIMARY KEY,
+        available TEXT    NOT NULL,
+        held      TEXT    NOT NULL,
+        total     TEXT    NOT NULL,
+        locked    INTEGER NOT NULL,
+        status    TEXT    NOT NULL
+    );
+
+    CREATE TABLE transactions (
+        client  INTEGER NOT NULL REFERENCES accounts (client),
+        seq     INTEGER NOT NULL,
+        tx      INTEGER NOT NULL,
+        type    TEXT    NOT NULL,
+        amount  TEXT    NOT NULL,
+        dispute TEXT    NOT NULL,
+        disputed_amount TEXT,
+        PRIMARY KEY (client, seq)
+    );
+
+    CREATE TABLE audit (
+        client   INTEGER NOT NULL REFERENCES accounts (client),
+        id       INTEGER NOT NULL,
+        type     TEXT    NOT NULL,
+        operator TEXT    NOT NULL,
+        reason   TEXT    NOT NULL,
+        PRIMARY KEY (client, id)
+    );
+";
+
+/// Writes the final state of a book to a new SQLite database at `path`,
+/// replacing whatever was there, for analysis in SQL.
+///
+/// The `accounts` table holds the same columns as the CSV output. The
+/// `transactions` table holds every account's log, with `seq` being the
+/// position of a transaction in its account's log, along with its dispute
+/// state: `undisputed`, `disputed`, `resolved` or `charged_back`.
+/// `disputed_amount` is what a dispute holds, or what a chargeback reversed.
+/// The `audit` table holds every account's administrative transactions,
+/// by the ID their account gave them, along with who ordered them and why.
+///
+/// **NOTE:** Amounts are kept as text with 4 decimal places, just as in
+/// the CSV output, so they are exact. SQLite converts them on the fly for
+/// arithmetic, and `CAST(amount AS REAL)` works where it doesn't.
+pub fn sqlite<S: Storage, P: AsRef<Path>>(book: &ClientBook<S>, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    let mut conn = Connection::open(path).map_err(io::Error::other)?;
+    let db = conn.transaction().map_err(io::Error::other)?;
+    db.execute_batch(SCHEMA).map_err(io::Error::other)?;
+
+    {
+        let mut accounts = db
+            .prepare("INSERT INTO accounts VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+            .map_err(io::Error::other)?;
+        let mut transactions = db
+            .prepare("INSERT INTO transactions VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+            .map_err(io::Error::other)?;
+        let mut audit = db
+            .prepare("INSERT INTO audit VALUES (?1, ?2, ?3, ?4, ?5)")
+            .map_err(io::Error::other)?;
+
+        book.for_each_account(|account| {
+            let client = account.id().get();
+            accounts
+                .execute(params![
+                    client,
+                    format_decimal(account.available()),
+                    format_decimal(account.held()),
+                    format_decimal(account.total()),
+                    account.locked(),
+                    account.status().as_str(),
+                ])
+                .map_err(io::Error::other)?;
+
+            for (seq, entry) in account.log().enumerate() {
+                let tx = entry.tx;
+                let amount = match tx.ty {
+                    TransactionType::Deposit { amount }
+                    | TransactionType::Withdrawal { amount } => amount,
+                    _ => unreachable!("only deposits and withdrawals are logged"),
+                };
+                let (state, disputed_amount) = match entry.dispute {
+                    DisputeState::Undisputed => ("undisputed", None),
+                    DisputeState::Disputed { held } => ("disputed", Some(held)),
+                    DisputeState::Resolved => ("resolved", None),
+                    DisputeState::ChargedBack { amount } => ("charged_back", Some(amount)),
+                };
+
+                transactions
+                    .execute(params![
+                        client,
+                        seq,
+                        tx.id.get(),
+                        tx.ty.name(),
+                        format_decimal(amount),
+                        state,
+                        disputed_amount.map(format_decimal),
+                    ])
+                    .map_err(io::Error::other)?;
+            }
+
+            for tx in account.audit() {
+                audit
+                    .execute(params![
+                        client,
+                        tx.id,
+                        tx.ty.name(),
+                        tx.authorization.operator,
+                        tx.authorization.reason,
+                    ])
+                    .map_err(io::Error::other)?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    db.commit().map_err(io::Error::other)
+}
+
+fn format_decimal(dec: rust_decimal::Decimal) -> String {
+    format!("{dec:.4}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Authorization, ClientId};
+
+    #[test]
+    fn exports_accounts_and_logs_in_order() {
+        let mut book = ClientBook::from_reader(
+            "type,client,tx,amount\n\
+             deposit,2,9,10.0\n\
+             deposit,1,3,5.0\n\
+             deposit,2,4,1.5\n\
+             withdrawal,2,1,2.0\n\
+             dispute,2,4,\n"
+                .as_bytes(),
+        )
+        .expect("input is valid");
+        let authorization = Authorization {
+            operator: "risk-7".to_owned(),
+            reason: "suspicious activity".to_owned(),
+        };
+        let outcome = book.administer(ClientId::new(1), TransactionType::Freeze, authorization);
+        assert!(outcome.unwrap().is_applied());
+
+        let path = std::env::temp_dir().join(format!("payx-{}-export.db", std::process::id()));
+        sqlite(&book, &path).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let accounts: Vec<(u16, String, String, String, bool)> = conn
+            .prepare("SELECT * FROM accounts ORDER BY client")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            accounts,
+            [
+                (1, "5.0000".into(), "0.0000".into(), "5.0000".into(), false),
+                (2, "8.0000".into(), "1.5000".into(), "9.5000".into(), false),
+            ]
+        );
+
+        let log: Vec<(u32, String, String, Option<String>)> = conn
+            .prepare("SELECT tx, type, dispute, disputed_amount FROM transactions WHERE client = 2 ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            log,
+            [
+                (9, "deposit".into(), "undisputed".into(), None),
+                (
+                    4,
+                    "deposit".into(),
+                    "disputed".into(),
+                    Some("1.5000".into())
+                ),
+                (1, "withdrawal".into(), "undisputed".into(), None),
+            ]
+        );
+
+        let audit: (u16, u32, String, String, String) = conn
+            .query_row("SELECT * FROM audit", [], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap();
+        assert_eq!(
+            audit,
+            (
+                1,
+                1,
+                "freeze".into(),
+                "risk-7".into(),
+                "suspicious activity".into()
+            )
+        );
+
+        drop(conn);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    io::{self, Read},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    ClientBook,
+    client::{TransactionError, TxOutcome},
+    storage::Storage,
+    transaction::{ClientId, Transaction},
+};
+
+/// How many requests are handled at once.
+const WORKERS: usize = 4;
+
+/// The largest request body accepted, in bytes.
+const MAX_BODY: u64 = 16 * 1024 * 1024;
+
+/// Serves an HTTP/JSON API for `book` on `listener`, until it fails.
+///
+/// * `POST /transactions` takes a transaction, or an array of them, as
+///   in NDJSON input, such as `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`.
+/// * `GET /clients` lists all accounts, as in the JSON output.
+/// * `GET /clients/{id}` returns a single account.
+///
+/// A single transaction is answered with its [`Outcome`], with a status
+/// code telling how it went, see [`status_code`]. Batches apply in order,
+/// and are answered with the outcome of each transaction, and `200 OK`
+/// when every transaction applied or was ignored, or `207 Multi-Status`
+/// when any of them failed.
+pub fn serve<S>(listener: TcpListener, book: Arc<Mutex<ClientBook<S>>>) -> io::Result<()>
+where
+    S: Storage + Send + 'static,
+{
+    let server = Arc::new(Server::from_listener(listener, None).map_err(io::Error::other)?);
+
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let book = Arc::clone(&book);
+
+            thread::spawn(move || -> io::Result<()> {
+                loop {
+                    let mut request = server.recv()?;
+                    let (status, body) = route(&mut request, &book);
+
+                    let response = Response::from_data(body)
+                        .with_status_code(status)
+                        .with_header(
+                            Header::from_bytes("Content-Type", "application/json")
+                                .expect("header is valid"),
+                        );
+                    if let Err(err) = request.respond(response) {
+                        eprintln!("failed to respond to a request: {err}");
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+    }
+
+    Ok(())
+}
+
+/// The outcome of a transaction, as it is answered.
+///
+/// ```json
+/// {"status":"rejected","code":"not_enough_balance","message":"not enough balance to withdraw"}
+/// ```
+///
+/// `code` and `message` are left out for applied transactions, codes
+/// are the same as in the rejects file.
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    /// `applied`, `ignored`, `rejected`, `malformed` or `error`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Outcome {
+    fn new(status: &'static str, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code: Some(code),
+            message: Some(message.to_string()),
+        }
+    }
+
+    fn applied(outcome: TxOutcome) -> (u16, Self) {
+        match outcome {
+            TxOutcome::Applied => (
+                200,
+                Self {
+                    status: "applied",
+                    code: None,
+                    message: None,
+                },
+            ),
+            TxOutcome::Ignored(reason) => (200, Self::new("ignored", reason.code(), reason)),
+            TxOutcome::Rejected(err) => {
+                (status_code(&err), Self::new("rejected", err.code(), &err))
+            }
+        }
+    }
+}
+
+/// The HTTP status code a rejected transaction is answered with.
+///
+/// **NOTE:** Ignored transactions are not failures, see
+/// [`crate::client::IgnoreReason`], so they are answered with `200 OK`.
+pub fn status_code(err: &TransactionError) -> u16 {
+    match err {
+        // Forbidden, the account's status or the sender doesn't allow it.
+        TransactionError::LockedAccount
+        | TransactionError::FrozenAccount
+        | TransactionError::ClosedAccount
+        | TransactionError::AdminOnly => 403,
+        // Conflict, with what the book already holds.
+        TransactionError::DuplicateTransactionId { .. }
+        | TransactionError::AlreadyChargedBack
+        | TransactionError::AlreadyResolved
+        | TransactionError::Escalated(_) => 409,
+        // Unprocessable, the transaction is fine but can't apply.
+        TransactionError::NotEnoughBalance
+        | TransactionError::AmountCannotBeNegative
+        | TransactionError::InvalidDisputeAmount
+        | TransactionError::DisputeExceedsAmount
+        | TransactionError::MissingOperator
+        | TransactionError::BalanceNotZero => 422,
+    }
+}
+
+/// Handles a request, returning the status code and body to answer with.
+fn route<S: Storage>(request: &mut Request, book: &Mutex<ClientBook<S>>) -> (u16, Vec<u8>) {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+
+    match (request.method(), segments.as_slice()) {
+        (Method::Post, ["transactions"]) => match read_body(request) {
+            Ok(body) => post_transactions(&body, book),
+            Err(err) => err,
+        },
+        (Method::Get, ["clients"]) => get_clients(book),
+        (Method::Get, ["clients", id]) => match id.parse().map(ClientId::new) {
+            Ok(client) => get_client(client, book),
+            Err(_) => failure(400, "malformed", format!("invalid client {id:?}")),
+        },
+        (_, ["transactions"] | ["clients"] | ["clients", _]) => {
+            failure(405, "method_not_allowed", "method not allowed")
+        }
+        _ => failure(404, "not_found", "no such resource"),
+    }
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>, (u16, Vec<u8>)> {
+    let mut body = vec![];
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)
+        .map_err(|err| failure(400, "malformed", err))?;
+
+    if body.len() as u64 > MAX_BODY {
+        return Err(failure(413, "too_large", "request body is too large"));
+    }
+    Ok(body)
+}
+
+fn post_transactions<S: Storage>(body: &[u8], book: &Mutex<ClientBook<S>>) -> (u16, Vec<u8>) {
+    let value: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(err) => return failure(400, "malformed", format!("invalid JSON: {err}")),
+    };
+
+    let Ok(mut book) = book.lock() else {
+        return failure(500, "error", "book is unavailable after an earlier failure");
+    };
+
+    // **NOTE:** Batch entries are read one by one, so a malformed one is
+    // reported on its own rather than failing the whole batch.
+    let (batch, values) = match value {
+        serde_json::Value::Array(values) => (true, values),
+        value => (false, vec![value]),
+    };
+
+    let outcomes: Vec<_> = values
+        .into_iter()
+        .map(|value| match serde_json::from_value::<Transaction>(value) {
+            Ok(tx) => match book.append_tx(tx) {
+                Ok(outcome) => Outcome::applied(outcome),
+                Err(err) => (500, Outcome::new("error", "error", err)),
+            },
+            Err(err) => {
+                book.count_malformed();
+                (400, Outcome::new("malformed", "malformed", err))
+            }
+        })
+        .collect();
+
+    match (batch, outcomes.as_slice()) {
+        (false, [(status, outcome)]) => (*status, json(outcome)),
+        _ => (
+            if outcomes.iter().all(|(status, _)| *status == 200) {
+                200
+            } else {
+                207
+            },
+            json(
+                &outcomes
+                    .iter()
+                    .map(|(_, outcome)| outcome)
+                    .collect::<Vec<_>>(),
+            ),
+        ),
+    }
+}
+
+fn get_clients<S: Storage>(book: &Mutex<ClientBook<S>>) -> (u16, Vec<u8>) {
+    let Ok(book) = book.lock() else {
+        return failure(500, "error", "book is unavailable after an earlier failure");
+    };
+
+    let mut body = b"[".to_vec();
+    let result = book.for_each_account(|account| {
+        if body.len() > 1 {
+            body.push(b',');
+        }
+        Ok(serde_json::to_writer(&mut body, account)?)
+    });
+    body.push(b']');
+
+    match result {
+        Ok(()) => (200, body),
+        Err(err) => failure(500, "error", err),
+    }
+}
+
+fn get_client<S: Storage>(client: ClientId, book: &Mutex<ClientBook<S>>) -> (u16, Vec<u8>) {
+    let Ok(book) = book.lock() else {
+        return failure(500, "error", "book is unavailable after an earlier failure");
+    };
+
+    match book.account(client) {
+        Ok(Some(account)) => (200, json(&account)),
+        Ok(None) => failure(404, "unknown_client", format!("unknown client {client}")),
+        Err(err) => failure(500, "error", err),
+    }
+}
+
+fn failure(status: u16, code: &'static str, message: impl ToString) -> (u16, Vec<u8>) {
+    let outcome = Outcome::new(
+        if status == 400 { "malformed" } else { "error" },
+        code,
+        message,
+    );
+    (status, json(&outcome))
+}
+
+fn json<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("responses always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{Shutdown, TcpStream},
+    };
+
+    use super::*;
+
+    /// Sends a request, returning the status code and body of the answer.
+    fn request(addr: std::net::SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, body.to_owned())
+    }
+
+    #[test]
+    fn answers_transactions_and_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let book = Arc::new(Mutex::new(ClientBook::default()));
+        thread::spawn(move || serve(listener, book));
+
+        let deposit = r#"{"type":"deposit","client":1,"tx":1,"amount":"10.0"}"#;
+        assert_eq!(
+            request(addr, "POST", "/transactions", deposit),
+            (200, r#"{"status":"applied"}"#.to_owned())
+        );
+        assert_eq!(request(addr, "POST", "/transactions", deposit).0, 409);
+
+        let withdrawal = r#"{"type":"withdrawal","client":1,"tx":2,"amount":"50.0"}"#;
+        assert_eq!(
+            request(addr, "POST", "/transactions", withdrawal),
+            (
+                422,
+                r#"{"status":"rejected","code":"not_enough_balance","message":"not enough balance to withdraw"}"#
+                    .to_owned()
+            )
+        );
+
+        let batch = r#"[
+            {"type":"withdrawal","client":1,"tx":3,"amount":"2.5"},
+            {"type":"dispute","client":2,"tx":9},
+            {"type":"refund","client":1,"tx":4}
+        ]"#;
+        let (status, body) = request(addr, "POST", "/transactions", batch);
+        assert_eq!(status, 207);
+        let outcomes: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let statuses: Vec<_> = outcomes.iter().map(|outcome| &outcome["status"]).collect();
+        assert_eq!(statuses, ["applied", "ignored", "malformed"]);
+
+        let failing = r#"[
+            {"type":"withdrawal","client":1,"tx":5,"amount":"100"},
+            {"type":"deposit","client":1,"tx":1,"amount":"1"}
+        ]"#;
+        let (status, body) = request(addr, "POST", "/transactions", failing);
+        assert_eq!(status, 207);
+        let outcomes: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        let codes: Vec<_> = outcomes.iter().map(|outcome| &outcome["code"]).collect();
+        assert_eq!(codes, ["not_enough_balance", "duplicate_transaction_id"]);
+
+        let passing = r#"[{"type":"deposit","client":2,"tx":6,"amount":"1"}]"#;
+        assert_eq!(request(addr, "POST", "/transactions", passing).0, 200);
+
+        assert_eq!(request(addr, "POST", "/transactions", "{").0, 400);
+
+        let (status, body) = request(addr, "GET", "/clients/1", "");
+        assert_eq!(status, 200);
+        let account: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(account["available"], "7.5000");
+
+        assert_eq!(request(addr, "GET", "/clients/2", "").0, 200);
+        assert_eq!(request(addr, "GET", "/clients/3", "").0, 404);
+        assert_eq!(request(addr, "GET", "/clients/x", "").0, 400);
+
+        let (status, body) = request(addr, "GET", "/clients", "");
+        assert_eq!(status, 200);
+        let accounts: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(accounts.len(), 2);
+
+        assert_eq!(request(addr, "DELETE", "/clients", "").0, 405);
+        assert_eq!(request(addr, "GET", "/accounts", "").0, 404);
+    }
+}
@@ -0,0 +1,1146 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{self, File},
+    io::{self, BufRead, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde::{Deserialize, de::value::StrDeserializer};
+
+use crate::{
+    ClientBook,
+    rejection::{Rejection, RejectionReason},
+    schema::{Schema, TypeName},
+    storage::Storage,
+    transaction::Transaction,
+};
+
+/// How input is read into a [`ClientBook`].
+#[derive(Clone, Debug, Default)]
+pub struct IngestOptions {
+    /// Aborts on the first malformed row, instead of reporting it
+    /// and moving on to the next one.
+    pub strict: bool,
+    /// When set, rows from all inputs are merged in the order of this
+    /// column, instead of reading one input after the other.
+    ///
+    /// Each input must already be sorted by it.
+    pub sequence_column: Option<String>,
+    /// The format of all inputs. When unset, it is guessed from each
+    /// input's file extension, falling back to CSV.
+    pub format: Option<InputFormat>,
+    /// The layout of CSV inputs.
+    pub schema: Schema,
+}
+
+/// The format transactions are read in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Rows with a header, as in `type,client,tx,amount`.
+    #[default]
+    Csv,
+    /// One JSON object per line, with the same fields as CSV rows.
+    Ndjson,
+}
+
+impl InputFormat {
+    /// Guesses the format from a file extension, looking past
+    /// compression extensions such as `.gz`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let mut path = path.to_owned();
+        while let Some(extension) = path.extension() {
+            match extension.to_str()? {
+                "csv" => return Some(Self::Csv),
+                "ndjson" | "jsonl" => return Some(Self::Ndjson),
+                "gz" | "zst" => path.set_extension(""),
+                _ => return None,
+            };
+        }
+        None
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown input format {0:?}, expected csv or ndjson")]
+pub struct UnknownInputFormat(String);
+
+impl FromStr for InputFormat {
+    type Err = UnknownInputFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(UnknownInputFormat(s.to_owned())),
+        }
+    }
+}
+
+/// A transaction input, such as a file or stdin.
+pub struct Input<'a> {
+    /// How rejections refer to this input, usually its path.
+    name: Option<String>,
+    /// The format implied by the input's name, if any.
+    format: Option<InputFormat>,
+    contents: Contents<'a>,
+}
+
+enum Contents<'a> {
+    Reader(Box<dyn Read + 'a>),
+    /// A file that is only opened once the input is read.
+    File(PathBuf),
+}
+
+impl<'a> Input<'a> {
+    /// The file at the given path.
+    ///
+    /// **NOTE:** The file is only opened when the input is read, so that
+    /// reading thousands of shards one after the other doesn't keep
+    /// thousands of files open.
+    pub fn open<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        Self {
+            name: Some(path.display().to_string()),
+            format: InputFormat::from_path(path),
+            contents: Contents::File(path.to_owned()),
+        }
+    }
+
+    pub fn named<R: Read + 'a>(name: impl Into<String>, reader: R) -> Self {
+        Self {
+            name: Some(name.into()),
+            format: None,
+            contents: Contents::Reader(Box::new(reader)),
+        }
+    }
+
+    /// An input without a name, such as an in-memory buffer.
+    pub fn unnamed<R: Read + 'a>(reader: R) -> Self {
+        Self {
+            name: None,
+            format: None,
+            contents: Contents::Reader(Box::new(reader)),
+        }
+    }
+}
+
+impl<'a> Contents<'a> {
+    fn into_reader(self) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Self::Reader(reader) => Ok(reader),
+            Self::File(path) => match File::open(&path) {
+                Ok(file) => Ok(Box::new(file)),
+                Err(err) => Err(io::Error::new(
+                    err.kind(),
+                    format!("failed to open {}: {err}", path.display()),
+                )),
+            },
+        }
+    }
+}
+
+/// Expands directories into the files they contain, sorted by their
+/// name, so hourly shards and the like apply in order. Files given
+/// on their own keep the order they were given in.
+///
+/// Hidden files are skipped when expanding directories.
+pub fn collect_files<P: AsRef<Path>>(paths: &[P]) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+
+    for path in paths {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            files.push(path.to_owned());
+            continue;
+        }
+
+        let mut entries = vec![];
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && entry.file_type()?.is_file() {
+                entries.push(entry.path());
+            }
+        }
+
+        entries.sort();
+        files.extend(entries);
+    }
+
+    Ok(files)
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wraps `reader` in a streaming decoder when its contents are gzip or
+/// zstd compressed, and leaves it as is otherwise.
+///
+/// Compression is detected from the leading magic bytes rather than the
+/// file extension, so it works just as well for stdin.
+pub fn decompress<'a, R>(mut reader: R) -> io::Result<Box<dyn Read + 'a>>
+where
+    R: Read + 'a,
+{
+    // **NOTE:** Pipes may hand us fewer bytes than asked for, so keep reading
+    // until the magic is complete or the input is over.
+    let mut magic = [0; 4];
+    let mut len = 0;
+    while len < magic.len() {
+        match reader.read(&mut magic[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    let magic = &magic[..len];
+    let reader = io::Cursor::new(magic.to_vec()).chain(reader);
+
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(flate2::read::MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(zstd::Decoder::new(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Where the transactions read from inputs go.
+pub(crate) trait Sink {
+    /// Appends a transaction, read from `row`, reporting the row to
+    /// `on_rejection` if it had no effect. Sinks applying transactions
+    /// elsewhere may report it on a later call.
+    fn append(
+        &mut self,
+        tx: Transaction,
+        row: Row,
+        on_rejection: &mut dyn FnMut(Rejection) -> io::Result<()>,
+    ) -> io::Result<()>;
+
+    /// Counts a row that could not be read as a transaction, see
+    /// [`crate::Counters::malformed`].
+    fn malformed(&mut self);
+}
+
+impl<S: Storage> Sink for ClientBook<S> {
+    fn append(
+        &mut self,
+        tx: Transaction,
+        row: Row,
+        on_rejection: &mut dyn FnMut(Rejection) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match RejectionReason::from_outcome(self.append_tx(tx)?) {
+            Some(reason) => on_rejection(row.rejection(reason)),
+            None => Ok(()),
+        }
+    }
+
+    fn malformed(&mut self) {
+        self.count_malformed();
+    }
+}
+
+/// Reads transactions from all `inputs`, appending them to `sink`.
+pub(crate) fn read<'a, K, I, F>(
+    sink: &mut K,
+    inputs: I,
+    options: &IngestOptions,
+    on_rejection: F,
+) -> io::Result<()>
+where
+    K: Sink,
+    I: IntoIterator<Item = Input<'a>>,
+    F: FnMut(Rejection) -> io::Result<()>,
+{
+    let mut ingest = Ingest {
+        sink,
+        options,
+        on_rejection,
+    };
+
+    if options.sequence_column.is_none() {
+        // Inputs are opened one at a time, as the previous one is done.
+        for input in inputs {
+            let mut source = Source::new(input, options)?;
+            while let Some(record) = source.next_record()? {
+                ingest.apply(&source, record)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    let mut sources = inputs
+        .into_iter()
+        .map(|input| Source::new(input, options))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // **NOTE:** A k-way merge, holding a single row per input at a time.
+    // Ties are broken by input order, so equal sequence numbers apply
+    // in the order the inputs were given.
+    let mut heads: Vec<Option<Record>> = (0..sources.len()).map(|_| None).collect();
+    let mut queue = BinaryHeap::with_capacity(sources.len());
+
+    for (idx, source) in sources.iter_mut().enumerate() {
+        if let Some((sequence, record)) = ingest.next_in_sequence(source)? {
+            heads[idx] = Some(record);
+            queue.push(Reverse((sequence, idx)));
+        }
+    }
+
+    while let Some(Reverse((_, idx))) = queue.pop() {
+        let Some(record) = heads[idx].take() else {
+            continue;
+        };
+
+        let source = &mut sources[idx];
+        ingest.apply(source, record)?;
+
+        if let Some((sequence, record)) = ingest.next_in_sequence(source)? {
+            heads[idx] = Some(record);
+            queue.push(Reverse((sequence, idx)));
+        }
+    }
+
+    Ok(())
+}
+
+/// An input being read, row by row.
+struct Source<'a> {
+    origin: Arc<Origin>,
+    reader: Reader<'a>,
+    /// The column holding sequence numbers, when merging inputs.
+    sequence_column: Option<String>,
+    last_sequence: Option<u64>,
+}
+
+/// What rows of a [`Source`] need to be read and reported on their own.
+struct Origin {
+    name: Option<String>,
+    /// The translated headers of CSV inputs, empty for NDJSON.
+    headers: csv::StringRecord,
+    /// Where the type column is in CSV inputs.
+    type_idx: Option<usize>,
+    /// Where the sequence column is in CSV inputs, when merging inputs.
+    sequence_idx: Option<usize>,
+}
+
+enum Reader<'a> {
+    Csv(csv::Reader<Box<dyn Read + 'a>>),
+    Ndjson {
+        lines: io::BufReader<Box<dyn Read + 'a>>,
+        /// The line number of the last line read.
+        line: u64,
+    },
+}
+
+/// A single row read from a [`Source`], along with where it came from.
+pub(crate) struct Row {
+    origin: Arc<Origin>,
+    record: Record,
+}
+
+impl Row {
+    pub(crate) fn rejection(&self, reason: RejectionReason) -> Rejection {
+        let name = self.origin.name.as_deref();
+        match &self.record {
+            Record::Csv { record, .. } => {
+                Rejection::from_record(name, &self.origin.headers, record, reason)
+            }
+            Record::Ndjson { line, value } => {
+                Rejection::from_json(name, *line, value.as_ref().ok(), reason)
+            }
+        }
+    }
+}
+
+enum Record {
+    Csv {
+        record: csv::StringRecord,
+        /// The first field that wasn't UTF-8, in which case `record` holds
+        /// a lossy copy of the row for reporting.
+        not_utf8: Option<usize>,
+    },
+    Ndjson {
+        line: u64,
+        value: Result<serde_json::Value, serde_json::Error>,
+    },
+}
+
+impl<'a> Source<'a> {
+    fn new(input: Input<'a>, options: &IngestOptions) -> io::Result<Self> {
+        let name = input.name;
+        let format = options.format.or(input.format).unwrap_or_default();
+        let decompressed = decompress(input.contents.into_reader()?)?;
+
+        let mut headers = csv::StringRecord::new();
+        let mut type_idx = None;
+        let mut sequence_idx = None;
+        let reader = match format {
+            InputFormat::Csv => {
+                let schema = &options.schema;
+                let mut reader = csv::ReaderBuilder::new()
+                    .trim(csv::Trim::All)
+                    // **NOTE:** Rows with missing or extra columns are read anyway, so
+                    // they fail when deserializing the transaction, and we still get to
+                    // report what the row looked like.
+                    .flexible(true)
+                    .delimiter(schema.delimiter())
+                    .quote(schema.quote())
+                    .quoting(schema.quoting)
+                    .has_headers(schema.headers.is_none())
+                    .from_reader(decompressed);
+
+                // **NOTE:** Headers are translated to ours right away, so that
+                // deserializing and reporting rows works the same for every layout.
+                let read: csv::StringRecord = match &schema.headers {
+                    Some(headers) => headers.iter().map(String::as_str).collect(),
+                    None => reader.headers()?.clone(),
+                };
+                headers = read
+                    .iter()
+                    .map(|header| schema.canonical_column(header))
+                    .collect();
+                type_idx = headers.iter().position(|header| header == "type");
+
+                if let Some(column) = &options.sequence_column {
+                    let idx = headers.iter().position(|header| header == column);
+                    sequence_idx = Some(idx.ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "{}: missing sequence column `{column}`",
+                                name.as_deref().unwrap_or("input")
+                            ),
+                        )
+                    })?);
+                }
+
+                Reader::Csv(reader)
+            }
+            InputFormat::Ndjson => Reader::Ndjson {
+                lines: io::BufReader::new(decompressed),
+                line: 0,
+            },
+        };
+
+        Ok(Self {
+            origin: Arc::new(Origin {
+                name,
+                headers,
+                type_idx,
+                sequence_idx,
+            }),
+            reader,
+            sequence_column: options.sequence_column.clone(),
+            last_sequence: None,
+        })
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<Record>> {
+        match &mut self.reader {
+            Reader::Csv(reader) => {
+                // **NOTE:** Rows are read as bytes, so that one which isn't
+                // UTF-8 is reported as malformed rather than ending the run.
+                let mut bytes = csv::ByteRecord::new();
+                if !reader.read_byte_record(&mut bytes)? {
+                    return Ok(None);
+                }
+                Ok(Some(match csv::StringRecord::from_byte_record(bytes) {
+                    Ok(record) => Record::Csv {
+                        record,
+                        not_utf8: None,
+                    },
+                    Err(err) => {
+                        let field = err.utf8_error().field();
+                        let bytes = err.into_byte_record();
+                        let position = bytes.position().cloned();
+                        let mut record = csv::StringRecord::from_byte_record_lossy(bytes);
+                        record.set_position(position);
+                        Record::Csv {
+                            record,
+                            not_utf8: Some(field),
+                        }
+                    }
+                }))
+            }
+            Reader::Ndjson { lines, line } => {
+                let mut buf = vec![];
+                loop {
+                    buf.clear();
+                    if lines.read_until(b'\n', &mut buf)? == 0 {
+                        return Ok(None);
+                    }
+                    *line += 1;
+
+                    // Blank lines, such as a trailing one, carry nothing.
+                    // Lines that aren't UTF-8 fail to parse like any other
+                    // invalid JSON.
+                    let json = buf.trim_ascii();
+                    if !json.is_empty() {
+                        return Ok(Some(Record::Ndjson {
+                            line: *line,
+                            value: serde_json::from_slice(json),
+                        }));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads the transaction in `record`, describing what's wrong with
+    /// it when it is malformed.
+    fn transaction(&self, record: &Record, schema: &Schema) -> Result<Transaction, String> {
+        match record {
+            Record::Csv {
+                not_utf8: Some(field),
+                ..
+            } => Err(match self.origin.headers.get(*field) {
+                Some(header) => format!("field `{header}` is not valid UTF-8"),
+                None => "row is not valid UTF-8".to_owned(),
+            }),
+            Record::Csv { record, .. } => {
+                let headers = &self.origin.headers;
+                // Only the type is translated, the row itself is kept as it
+                // was read for reporting.
+                let translated;
+                let type_idx = self.origin.type_idx;
+                let record = match type_idx.and_then(|idx| Some((idx, record.get(idx)?))) {
+                    Some((idx, ty)) if schema.canonical_type(ty) != ty => {
+                        let canonical = schema.canonical_type(ty);
+                        translated = record
+                            .iter()
+                            .enumerate()
+                            .map(|(i, field)| if i == idx { canonical } else { field })
+                            .collect::<csv::StringRecord>();
+                        &translated
+                    }
+                    _ => record,
+                };
+
+                record
+                    .deserialize(Some(headers))
+                    .map_err(|err| malformed_message(headers, record, &err))
+            }
+            Record::Ndjson { value, .. } => match value {
+                Ok(value) => Transaction::deserialize(value).map_err(|err| {
+                    let message = err.to_string();
+                    match flattened_column(&message, value.get("type").and_then(|ty| ty.as_str())) {
+                        Some(field) => format!("field `{field}`: {message}"),
+                        None => message,
+                    }
+                }),
+                Err(err) => Err(invalid_json_message(err)),
+            },
+        }
+    }
+
+    /// The sequence number of `record`, if it has a valid one.
+    fn sequence(&self, record: &Record) -> Option<u64> {
+        match record {
+            Record::Csv { record, .. } => self
+                .origin
+                .sequence_idx
+                .and_then(|idx| record.get(idx))
+                .and_then(|sequence| sequence.parse().ok()),
+            Record::Ndjson { value, .. } => {
+                let sequence = value.as_ref().ok()?.get(self.sequence_column.as_deref()?)?;
+                match sequence {
+                    serde_json::Value::String(sequence) => sequence.parse().ok(),
+                    sequence => sequence.as_u64(),
+                }
+            }
+        }
+    }
+
+    fn row(&self, record: Record) -> Row {
+        Row {
+            origin: Arc::clone(&self.origin),
+            record,
+        }
+    }
+}
+
+/// Everything needed to apply rows to a sink.
+struct Ingest<'k, 'o, K, F> {
+    sink: &'k mut K,
+    options: &'o IngestOptions,
+    on_rejection: F,
+}
+
+impl<K, F> Ingest<'_, '_, K, F>
+where
+    K: Sink,
+    F: FnMut(Rejection) -> io::Result<()>,
+{
+    /// Appends the transaction in `record` to the sink, reporting it
+    /// if it had no effect.
+    fn apply(&mut self, source: &Source, record: Record) -> io::Result<()> {
+        let tx = match source.transaction(&record, &self.options.schema) {
+            Ok(tx) => tx,
+            Err(message) => return self.malformed(source.row(record), message),
+        };
+
+        self.sink
+            .append(tx, source.row(record), &mut self.on_rejection)
+    }
+
+    /// Reads the next row of a source along with its sequence number,
+    /// reporting rows without a valid one.
+    fn next_in_sequence(&mut self, source: &mut Source) -> io::Result<Option<(u64, Record)>> {
+        while let Some(record) = source.next_record()? {
+            let message = match (source.sequence(&record), source.last_sequence) {
+                (None, _) => "missing or invalid sequence number".to_owned(),
+                (Some(sequence), Some(last)) if sequence < last => {
+                    format!("sequence number {sequence} comes after {last}")
+                }
+                (Some(sequence), _) => {
+                    source.last_sequence = Some(sequence);
+                    return Ok(Some((sequence, record)));
+                }
+            };
+
+            self.malformed(source.row(record), message)?;
+        }
+
+        Ok(None)
+    }
+
+    /// Reports a malformed row, or fails right away in strict mode.
+    fn malformed(&mut self, row: Row, message: String) -> io::Result<()> {
+        let rejection = row.rejection(RejectionReason::Malformed(message));
+        self.sink.malformed();
+
+        if self.options.strict {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", rejection.location(), rejection.reason),
+            ));
+        }
+
+        (self.on_rejection)(rejection)
+    }
+}
+
+/// Reads a single CSV row in our own layout, `type,client,tx,amount`,
+/// describing what's wrong with it when it is malformed.
+pub(crate) fn parse_row(row: &str) -> Result<Transaction, String> {
+    let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(row.as_bytes());
+
+    let mut record = csv::StringRecord::new();
+    match reader.read_record(&mut record) {
+        Ok(true) => record
+            .deserialize(Some(&headers))
+            .map_err(|err| malformed_message(&headers, &record, &err)),
+        Ok(false) => Err("empty row".to_owned()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Describes why a line is not valid JSON.
+///
+/// **NOTE:** Each line is parsed on its own, so the line `serde_json`
+/// reports is always 1. The real one is already in the rejection's
+/// location, so only the column is kept.
+fn invalid_json_message(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+    let message = message
+        .rfind(" at line ")
+        .map_or(message.as_str(), |idx| &message[..idx]);
+
+    format!("invalid JSON: {message} at column {}", err.column())
+}
+
+/// Describes why a row could not be deserialized, naming the offending
+/// column when `csv` knows which one it is.
+///
+/// **NOTE:** `csv` does not know the column for errors raised inside
+/// the flattened [`crate::transaction::TransactionType`], but those
+/// messages (e.g. unknown variants) already point at what's wrong.
+fn malformed_message(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+    err: &csv::Error,
+) -> String {
+    let csv::ErrorKind::Deserialize { err, .. } = err.kind() else {
+        return err.to_string();
+    };
+
+    let message = err.kind().to_string();
+    let field = match err.kind() {
+        // Short rows end right before the first missing column.
+        csv::DeserializeErrorKind::UnexpectedEndOfRow => headers.get(record.len()),
+        _ => match err.field() {
+            Some(idx) => headers.get(idx as usize),
+            None => {
+                let ty = headers
+                    .iter()
+                    .position(|header| header == "type")
+                    .and_then(|idx| record.get(idx));
+                flattened_column(&message, ty)
+            }
+        },
+    };
+
+    match field {
+        Some(field) => format!("field `{field}`: {message}"),
+        None => message,
+    }
+}
+
+/// The column an error without a field is about, given the row's type.
+///
+/// **NOTE:** The type and amount are read into a flattened enum, so serde
+/// can't tell which field its errors come from. An unknown type is on the
+/// `type` column, anything else wrong with a known one is on its `amount`.
+/// Missing fields already name themselves.
+fn flattened_column(message: &str, ty: Option<&str>) -> Option<&'static str> {
+    if message.starts_with("missing field") {
+        return None;
+    }
+
+    let known = ty.is_some_and(|ty| {
+        TypeName::deserialize(StrDeserializer::<serde::de::value::Error>::new(ty)).is_ok()
+    });
+    Some(if known { "amount" } else { "type" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{TransactionError, TxOutcome},
+        transaction::ClientId,
+    };
+
+    const MALFORMED: &str = "\
+type,client,tx,amount
+deposit,1,1,10.0
+refund,1,2,1.0
+deposit,70000,3,1.0
+withdrawal,1,4
+withdrawal,1,5,4.0
+";
+
+    #[test]
+    fn lenient_reports_malformed_rows_and_carries_on() {
+        let mut rejections = vec![];
+
+        let book = ClientBook::from_reader_with(
+            MALFORMED.as_bytes(),
+            &IngestOptions::default(),
+            |rejection| {
+                rejections.push(rejection);
+                Ok(())
+            },
+        )
+        .expect("lenient ingestion never fails on malformed rows");
+
+        let lines: Vec<_> = rejections.iter().map(|r| r.line).collect();
+        assert_eq!(lines, [3, 4, 5]);
+        assert!(
+            rejections
+                .iter()
+                .all(|r| matches!(r.reason, RejectionReason::Malformed(_)))
+        );
+
+        let clients = book.into_clients();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].available(), rust_decimal::dec!(6.0));
+    }
+
+    #[test]
+    fn merges_inputs_by_sequence_column() {
+        // Withdrawing before both deposits land would fail.
+        let first = "type,client,tx,amount,seq\ndeposit,1,1,10.0,1\nwithdrawal,1,3,15.0,3\n";
+        let second = "type,client,tx,amount,seq\ndeposit,1,2,10.0,2\nwithdrawal,1,4,1.0,1\n";
+
+        let options = IngestOptions {
+            sequence_column: Some("seq".to_owned()),
+            ..Default::default()
+        };
+        let mut rejections = vec![];
+
+        let mut book = ClientBook::default();
+        book.ingest(
+            [
+                Input::named("first.csv", first.as_bytes()),
+                Input::named("second.csv", second.as_bytes()),
+            ],
+            &options,
+            |rejection| {
+                rejections.push(rejection);
+                Ok(())
+            },
+        )
+        .expect("inputs are valid");
+
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].location(), "second.csv:3");
+        assert!(matches!(
+            rejections[0].reason,
+            RejectionReason::Malformed(_)
+        ));
+
+        let clients = book.into_clients();
+        assert_eq!(clients[0].available(), rust_decimal::dec!(5.0));
+    }
+
+    #[test]
+    fn rejects_transaction_ids_used_by_another_client() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,1,5.0\n\
+                     withdrawal,2,2,1.0\n\
+                     dispute,2,1,\n";
+        let mut rejections = vec![];
+
+        let book = ClientBook::from_reader_with(
+            input.as_bytes(),
+            &IngestOptions::default(),
+            |rejection| {
+                rejections.push(rejection);
+                Ok(())
+            },
+        )
+        .expect("input is valid");
+
+        assert_eq!(rejections.len(), 3);
+        assert!(matches!(
+            rejections[0].reason,
+            RejectionReason::NoEffect(TxOutcome::Rejected(
+                TransactionError::DuplicateTransactionId { owner }
+            ))
+                if owner == ClientId::new(1)
+        ));
+        assert_eq!(rejections[1].reason.code(), "not_enough_balance");
+        assert_eq!(rejections[2].reason.code(), "cross_client_dispute");
+
+        let clients = book.into_clients();
+        assert_eq!(clients[0].available(), rust_decimal::dec!(10.0));
+        assert_eq!(clients[1].available(), rust_decimal::dec!(0.0));
+    }
+
+    #[test]
+    fn classifies_disputes_of_other_clients_by_policy() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,5.0\n\
+                     dispute,2,1,\n\
+                     chargeback,2,1,\n";
+
+        for (policy, ignored) in [
+            (crate::CrossClientPolicy::Ignore, true),
+            (crate::CrossClientPolicy::Reject, false),
+        ] {
+            let mut book = ClientBook::default();
+            book.set_cross_client_policy(policy);
+
+            let mut rejections = vec![];
+            book.ingest(
+                [Input::unnamed(input.as_bytes())],
+                &IngestOptions::default(),
+                |rejection| {
+                    rejections.push(rejection);
+                    Ok(())
+                },
+            )
+            .expect("input is valid");
+
+            assert_eq!(rejections.len(), 2);
+            for rejection in &rejections {
+                assert_eq!(rejection.reason.code(), "cross_client_dispute");
+                assert_eq!(
+                    matches!(
+                        rejection.reason,
+                        RejectionReason::NoEffect(TxOutcome::Ignored(_))
+                    ),
+                    ignored
+                );
+            }
+            assert!(
+                rejections[0]
+                    .to_string()
+                    .ends_with("referenced transaction belongs to client 1")
+            );
+
+            let clients = book.into_clients();
+            assert_eq!(clients[0].held(), rust_decimal::dec!(0.0));
+            assert!(!clients[1].locked());
+        }
+    }
+
+    const SAMPLE: &str = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n";
+
+    fn decompressed(input: &[u8]) -> String {
+        let mut out = String::new();
+        decompress(input)
+            .and_then(|mut reader| reader.read_to_string(&mut out))
+            .expect("input is valid");
+        out
+    }
+
+    #[test]
+    fn decompress_passes_plain_input_through() {
+        assert_eq!(decompressed(SAMPLE.as_bytes()), SAMPLE);
+        assert_eq!(decompressed(b"ab"), "ab");
+        assert_eq!(decompressed(b""), "");
+    }
+
+    #[test]
+    fn decompress_detects_gzip() {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        io::Write::write_all(&mut encoder, SAMPLE.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompressed(&compressed), SAMPLE);
+    }
+
+    #[test]
+    fn decompress_detects_zstd() {
+        let compressed = zstd::encode_all(SAMPLE.as_bytes(), 0).unwrap();
+
+        assert_eq!(decompressed(&compressed), SAMPLE);
+    }
+
+    #[test]
+    fn reads_ndjson_with_the_same_rejections() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"10.0"}
+{"type":"withdrawal","client":1,"tx":2,"amount":4.5}
+
+{"type":"refund","client":1,"tx":3,"amount":1}
+{"type":"withdrawal","client":1,"tx":4,"amount":100}
+{"type":"deposit","client":1,
+"#;
+        let options = IngestOptions {
+            format: Some(InputFormat::Ndjson),
+            ..Default::default()
+        };
+        let mut rejections = vec![];
+
+        let book = ClientBook::from_reader_with(input.as_bytes(), &options, |rejection| {
+            rejections.push(rejection);
+            Ok(())
+        })
+        .expect("lenient ingestion never fails on malformed rows");
+
+        let reported: Vec<_> = rejections
+            .iter()
+            .map(|r| (r.line, r.reason.code(), r.amount.as_str()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                (4, "malformed", "1"),
+                (5, "not_enough_balance", "100"),
+                (6, "malformed", ""),
+            ]
+        );
+
+        let clients = book.into_clients();
+        assert_eq!(clients[0].available(), rust_decimal::dec!(5.5));
+    }
+
+    #[test]
+    fn reports_rows_that_are_not_utf8_and_carries_on() {
+        let csv =
+            b"type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,\xff1.0\ndeposit,1,3,2.0\n";
+        let ndjson = b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.0\"}
+{\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"\xff1.0\"}
+{\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":\"2.0\"}
+";
+        for (input, format, line) in [
+            (&csv[..], InputFormat::Csv, 3),
+            (&ndjson[..], InputFormat::Ndjson, 2),
+        ] {
+            let options = IngestOptions {
+                format: Some(format),
+                ..Default::default()
+            };
+            let mut rejections = vec![];
+
+            let book = ClientBook::from_reader_with(input, &options, |rejection| {
+                rejections.push(rejection);
+                Ok(())
+            })
+            .expect("lenient ingestion never fails on malformed rows");
+
+            let reported: Vec<_> = rejections
+                .iter()
+                .map(|r| (r.line, r.reason.code()))
+                .collect();
+            assert_eq!(reported, [(line, "malformed")]);
+            assert_eq!(book.into_clients()[0].available(), rust_decimal::dec!(12));
+
+            let options = IngestOptions {
+                strict: true,
+                ..options
+            };
+            let err = ClientBook::from_reader_with(input, &options, |_| Ok(()))
+                .expect_err("row is malformed");
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(
+                err.to_string().starts_with(&format!("line {line}: ")),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn reads_ndjson_numbers_without_going_through_floats() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":12345678901234.5678}"#;
+        let options = IngestOptions {
+            format: Some(InputFormat::Ndjson),
+            ..Default::default()
+        };
+
+        let book = ClientBook::from_reader_with(input.as_bytes(), &options, |rejection| {
+            panic!("{rejection}")
+        })
+        .expect("input is valid");
+
+        let clients = book.into_clients();
+        assert_eq!(
+            clients[0].available(),
+            rust_decimal::dec!(12345678901234.5678)
+        );
+    }
+
+    #[test]
+    fn reads_partner_layouts_through_a_schema() {
+        let schema = Schema::from_toml(
+            r#"
+            delimiter = ";"
+            headers = ["txn_id", "client_id", "kind", "value"]
+
+            [columns]
+            type = "kind"
+            client = "client_id"
+            tx = "txn_id"
+            amount = "value"
+
+            [types]
+            DEPOSIT = "deposit"
+            refund = "withdrawal"
+            "#,
+        )
+        .expect("schema is valid");
+        let options = IngestOptions {
+            schema,
+            ..Default::default()
+        };
+        let input = "1;1;DEPOSIT;10.0\n2;1;refund;4.0\n3;1;REFUND;1.0\n";
+        let mut rejections = vec![];
+
+        let book = ClientBook::from_reader_with(input.as_bytes(), &options, |rejection| {
+            rejections.push(rejection);
+            Ok(())
+        })
+        .expect("lenient ingestion never fails on malformed rows");
+
+        // Rejections show the row as the partner sent it.
+        assert_eq!(rejections.len(), 1);
+        assert_eq!(rejections[0].line, 3);
+        assert_eq!(rejections[0].ty, "REFUND");
+
+        let clients = book.into_clients();
+        assert_eq!(clients[0].available(), rust_decimal::dec!(6.0));
+    }
+
+    #[test]
+    fn guesses_format_from_extension() {
+        let format = |path: &str| InputFormat::from_path(Path::new(path));
+
+        assert_eq!(format("in.csv"), Some(InputFormat::Csv));
+        assert_eq!(format("in.ndjson"), Some(InputFormat::Ndjson));
+        assert_eq!(format("shards/in.jsonl.gz"), Some(InputFormat::Ndjson));
+        assert_eq!(format("in.csv.zst"), Some(InputFormat::Csv));
+        assert_eq!(format("in.txt"), None);
+        assert_eq!(format("in"), None);
+    }
+
+    #[test]
+    fn sorts_only_files_found_in_directories() {
+        let dir = std::env::temp_dir().join(format!("payx-{}-shards", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("day")).unwrap();
+        for name in [
+            "day/02.csv",
+            "day/01.csv",
+            "day/.hidden.csv",
+            "b.csv",
+            "a.csv",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let files = collect_files(&[dir.join("b.csv"), dir.join("day"), dir.join("a.csv")])
+            .expect("paths exist");
+        let _ = fs::remove_dir_all(&dir);
+
+        let expected = ["b.csv", "day/01.csv", "day/02.csv", "a.csv"].map(|name| dir.join(name));
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn opens_files_only_when_read() {
+        let mut book = ClientBook::default();
+        let err = book
+            .ingest(
+                [
+                    Input::unnamed("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes()),
+                    Input::open("missing.csv"),
+                ],
+                &IngestOptions::default(),
+                |_| Ok(()),
+            )
+            .expect_err("second input is missing");
+
+        assert!(
+            err.to_string().starts_with("failed to open missing.csv"),
+            "{err}"
+        );
+        assert_eq!(
+            book.counters().applied,
+            1,
+            "first input is read before opening the second"
+        );
+    }
+
+    #[test]
+    fn strict_aborts_with_line_and_field() {
+        let input = "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,70000,3,1.0\n";
+        let options = IngestOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        let err = ClientBook::from_reader_with(input.as_bytes(), &options, |_| {
+            panic!("strict ingestion does not report malformed rows")
+        })
+        .expect_err("row is malformed");
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let message = err.to_string();
+        assert!(message.starts_with("line 3: "), "{message}");
+        assert!(message.contains("field `client`"), "{message}");
+
+        for (row, field) in [("deposit,1,2,", "amount"), ("refund,1,2,1.0", "type")] {
+            let input = format!("type,client,tx,amount\n{row}\n");
+            let err = ClientBook::from_reader_with(input.as_bytes(), &options, |_| Ok(()))
+                .expect_err("row is malformed");
+            let message = err.to_string();
+            assert!(message.contains(&format!("field `{field}`")), "{message}");
+        }
+    }
+}
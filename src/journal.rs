@@ -0,0 +1,416 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use crate::transaction::Record;
+
+/// The size of a record header: the payload length and its CRC32,
+/// both as little endian u32s.
+const HEADER_LEN: u64 = 8;
+
+/// An append-only log of every transaction applied to a book, written
+/// before the book changes, so the book can be rebuilt after a crash.
+///
+/// Each record is a header followed by the transaction as JSON, see
+/// [`Record`]:
+///
+/// ```text
+/// [len: u32][crc32: u32][payload: len bytes]
+/// ```
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    /// Set when a failed append could not be undone, leaving a partial
+    /// record that later appends must not land after.
+    failed: bool,
+}
+
+impl Journal {
+    /// Opens the journal at the given path, creating it when missing, and
+    /// hands every transaction it holds to `replay`, in order.
+    ///
+    /// A crash while appending leaves a partial record at the end of the
+    /// journal. That record never reached the book, so it is discarded.
+    /// Damaged records anywhere else are an error.
+    pub fn open<P, F>(path: P, mut replay: F) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Record) -> io::Result<()>,
+    {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut reader = BufReader::new(&mut file);
+        let mut offset = 0;
+        let mut payload = vec![];
+
+        while let Some(record_len) = read_record(&mut reader, offset, len, &mut payload)? {
+            let record = serde_json::from_slice(&payload)
+                .map_err(|err| corrupt(offset, &format!("unreadable transaction: {err}")))?;
+            replay(record)?;
+
+            offset += record_len;
+        }
+
+        if offset < len {
+            file.set_len(offset)?;
+            file.sync_all()?;
+        }
+
+        Ok(Self {
+            file,
+            failed: false,
+        })
+    }
+
+    /// Durably appends a transaction. Once this returns, the transaction
+    /// survives a crash.
+    ///
+    /// When writing fails, whatever got written of the record is cut off
+    /// again. If even that fails, every later append fails too, as they
+    /// would land after a damaged record.
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        if self.failed {
+            return Err(io::Error::other(
+                "journal holds a partial record of an earlier failed append",
+            ));
+        }
+
+        write_record(&mut self.file, &encode(record)?, &mut self.failed)
+    }
+}
+
+/// A record as written to the journal, header included.
+fn encode(record: &Record) -> io::Result<Vec<u8>> {
+    let payload = serde_json::to_vec(record)?;
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record is too large"))?;
+
+    let mut record = Vec::with_capacity(HEADER_LEN as usize + payload.len());
+    record.extend_from_slice(&len.to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// What [`Journal::append`] needs from the file it writes to.
+trait JournalFile: Write {
+    fn len(&self) -> io::Result<u64>;
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+    fn sync_data(&mut self) -> io::Result<()>;
+}
+
+impl JournalFile for File {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    fn sync_data(&mut self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+/// Writes `record` at the end of `file`, truncating it back to where it
+/// was when that fails, and setting `failed` when even that fails.
+fn write_record<F: JournalFile>(file: &mut F, record: &[u8], failed: &mut bool) -> io::Result<()> {
+    let len = file.len()?;
+
+    let written = file.write_all(record).and_then(|()| file.sync_data());
+    if written.is_err() && file.set_len(len).and_then(|()| file.sync_data()).is_err() {
+        *failed = true;
+    }
+
+    written
+}
+
+/// Reads the record at `offset` into `payload`, returning its length
+/// including the header, or `None` when the journal, `end` bytes long,
+/// ends before or partway through it.
+fn read_record<R: Read>(
+    reader: &mut R,
+    offset: u64,
+    end: u64,
+    payload: &mut Vec<u8>,
+) -> io::Result<Option<u64>> {
+    if offset + HEADER_LEN > end {
+        return Ok(None);
+    }
+
+    let mut header = [0; HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    // **NOTE:** Checked before reading, so a torn header with a garbage
+    // length doesn't make us allocate gigabytes.
+    let record_len = HEADER_LEN + u64::from(len);
+    if offset + record_len > end {
+        return Ok(None);
+    }
+
+    payload.resize(len as usize, 0);
+    reader.read_exact(payload)?;
+
+    if crc32fast::hash(payload) != crc {
+        // A torn write may also show up as a complete record with garbage
+        // in it, but only if nothing follows it.
+        return match offset + record_len == end {
+            true => Ok(None),
+            false => Err(corrupt(offset, "checksum mismatch")),
+        };
+    }
+
+    Ok(Some(record_len))
+}
+
+fn corrupt(offset: u64, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("journal is corrupt at byte {offset}: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::{
+        ClientBook,
+        client::AccountStatus,
+        transaction::{
+            AdminTx, Authorization, ClientId, Transaction, TransactionId, TransactionType,
+        },
+    };
+
+    /// A journal path unique to each test, removed when dropped.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("payx-{}-{name}", std::process::id()));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn journaled(path: &Path, input: &str) -> ClientBook {
+        let mut book = ClientBook::open_journal(path).expect("journal is valid");
+        book.ingest(
+            [crate::ingest::Input::unnamed(input.as_bytes())],
+            &Default::default(),
+            |_| Ok(()),
+        )
+        .expect("input is valid");
+        book
+    }
+
+    const INPUT: &str = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         withdrawal,1,2,50.0\n\
+                         withdrawal,1,3,4.0\n\
+                         dispute,1,1,2.0\n";
+
+    #[test]
+    fn rebuilds_the_book_from_applied_transactions() {
+        let path = TempPath::new("rebuild");
+        drop(journaled(&path.0, INPUT));
+
+        let mut replayed = 0;
+        Journal::open(&path.0, |_| {
+            replayed += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(replayed, 3, "rejected withdrawal is not journaled");
+
+        let clients = ClientBook::open_journal(&path.0).unwrap().into_clients();
+        assert_eq!(clients[0].available(), dec!(4.0));
+        assert_eq!(clients[0].held(), dec!(2.0));
+    }
+
+    #[test]
+    fn keeps_who_ordered_administrative_transactions() {
+        let path = TempPath::new("administered");
+        let mut book = journaled(&path.0, INPUT);
+
+        let client = ClientId::new(1);
+        let authorization = Authorization {
+            operator: "risk-7".to_owned(),
+            reason: "suspicious activity".to_owned(),
+        };
+        let outcome = book.administer(client, TransactionType::Freeze, authorization);
+        assert!(outcome.unwrap().is_applied());
+        drop(book);
+
+        let book = ClientBook::open_journal(&path.0).unwrap();
+        let account = book.account(client).unwrap().unwrap();
+        assert!(matches!(
+            account.audit(),
+            [AdminTx { id: 1, ty: TransactionType::Freeze, authorization, .. }]
+                if authorization.operator == "risk-7"
+        ));
+        assert_eq!(account.status(), AccountStatus::Frozen);
+    }
+
+    #[test]
+    fn discards_a_truncated_tail() {
+        let path = TempPath::new("truncated");
+        drop(journaled(&path.0, INPUT));
+
+        let len = fs::metadata(&path.0).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path.0).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        // The dispute is lost, and later appends land right after the withdrawal.
+        let book = journaled(&path.0, "type,client,tx,amount\ndeposit,1,4,1.0\n");
+        let clients = book.into_clients();
+        assert_eq!(clients[0].available(), dec!(7.0));
+        assert_eq!(clients[0].held(), dec!(0.0));
+
+        let clients = ClientBook::open_journal(&path.0).unwrap().into_clients();
+        assert_eq!(clients[0].available(), dec!(7.0));
+    }
+
+    /// A file that fails writes once `budget` bytes went through, and
+    /// optionally fails truncating too.
+    struct FailingFile {
+        bytes: Vec<u8>,
+        budget: usize,
+        truncate_fails: bool,
+    }
+
+    impl Write for FailingFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.budget);
+            if len == 0 {
+                return Err(io::Error::other("disk is full"));
+            }
+            self.bytes.extend_from_slice(&buf[..len]);
+            self.budget -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl JournalFile for FailingFile {
+        fn len(&self) -> io::Result<u64> {
+            Ok(self.bytes.len() as u64)
+        }
+
+        fn set_len(&mut self, len: u64) -> io::Result<()> {
+            if self.truncate_fails {
+                return Err(io::Error::other("disk is gone"));
+            }
+            self.bytes.truncate(len as usize);
+            Ok(())
+        }
+
+        fn sync_data(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cuts_off_partial_records_of_failed_appends() {
+        let record = |id| {
+            Record::Tx(Transaction {
+                ty: TransactionType::Deposit { amount: dec!(1.0) },
+                client_id: ClientId::new(1),
+                id: TransactionId::new(id),
+            })
+        };
+        let first = encode(&record(1)).unwrap();
+        let second = encode(&record(2)).unwrap();
+        let third = encode(&record(3)).unwrap();
+
+        for truncate_fails in [false, true] {
+            let mut file = FailingFile {
+                bytes: vec![],
+                budget: first.len() + 5,
+                truncate_fails,
+            };
+            let mut failed = false;
+            write_record(&mut file, &first, &mut failed).unwrap();
+            write_record(&mut file, &second, &mut failed).expect_err("disk is full");
+            assert_eq!(failed, truncate_fails);
+
+            if !failed {
+                file.budget = usize::MAX;
+                write_record(&mut file, &third, &mut failed).unwrap();
+            }
+
+            let path = TempPath::new("failing");
+            fs::write(&path.0, &file.bytes).unwrap();
+            let mut replayed = vec![];
+            let result = Journal::open(&path.0, |record| {
+                if let Record::Tx(tx) = record {
+                    replayed.push(tx.id.get());
+                }
+                Ok(())
+            });
+
+            match truncate_fails {
+                false => assert_eq!(replayed, [1, 3]),
+                // The partial record is only discarded because nothing
+                // got appended after it.
+                true => {
+                    assert!(result.is_ok());
+                    assert_eq!(replayed, [1]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_appends_after_a_failed_one_it_could_not_undo() {
+        let path = TempPath::new("failed");
+        let mut journal = Journal::open(&path.0, |_| Ok(())).unwrap();
+        journal.failed = true;
+
+        let deposit = Transaction {
+            ty: TransactionType::Deposit { amount: dec!(1.0) },
+            client_id: ClientId::new(1),
+            id: TransactionId::new(1),
+        };
+        journal
+            .append(&Record::Tx(deposit))
+            .expect_err("journal failed");
+        assert_eq!(fs::metadata(&path.0).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn fails_on_damaged_records_before_the_tail() {
+        let path = TempPath::new("damaged");
+        drop(journaled(&path.0, INPUT));
+
+        let mut bytes = fs::read(&path.0).unwrap();
+        bytes[HEADER_LEN as usize] ^= 0xff;
+        fs::write(&path.0, bytes).unwrap();
+
+        let err = ClientBook::open_journal(&path.0).expect_err("first record is damaged");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
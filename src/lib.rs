@@ -1,13 +1,39 @@
-use std::path::Path;
+use std::{
+    collections::HashMap, io, mem, num::NonZeroUsize, ops::AddAssign, path::Path, str::FromStr,
+};
 
 use indexmap::IndexMap;
+use rust_decimal::Decimal;
+use serde::Serialize;
 
 use crate::{
-    client::{ClientAccount, TransactionError},
-    transaction::{ClientId, Transaction},
+    client::{ClientAccount, IgnoreReason, LogEntry, TransactionError, TxOutcome},
+    ingest::{IngestOptions, Input},
+    journal::Journal,
+    rejection::{Rejection, RejectionReason},
+    snapshot::{AccountSnapshot, Snapshot, SnapshotError},
+    storage::{MemoryStorage, Storage},
+    transaction::{Authorization, ClientId, Record, Transaction, TransactionId, TransactionType},
 };
 
+#[cfg(feature = "async")]
+pub mod actor;
+#[cfg(unix)]
+pub mod admin;
 pub mod client;
+#[cfg(feature = "sqlite")]
+pub mod export;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod ingest;
+pub mod journal;
+pub mod output;
+mod parallel;
+pub mod rejection;
+pub mod schema;
+pub mod server;
+pub mod snapshot;
+pub mod storage;
 pub mod transaction;
 
 /// A collection of clients.
@@ -21,46 +47,456 @@ pub mod transaction;
 /// is considerably small. But it would make the code harder
 /// to maintain and expand in the future, for little real gain,
 /// so I decided against it.
-#[derive(Debug, Default)]
-pub struct ClientBook {
-    clients: IndexMap<ClientId, ClientAccount>,
+///
+/// Accounts live in memory, unless another [`Storage`] is given
+/// through [`ClientBook::with_storage`].
+#[derive(Debug)]
+pub struct ClientBook<S = MemoryStorage> {
+    storage: S,
+    /// Where applied transactions are recorded, if anywhere.
+    journal: Option<Journal>,
+    cross_client: CrossClientPolicy,
+    counters: Counters,
+}
+
+/// How many transactions a book processed, by outcome, since it was
+/// created or restored.
+///
+/// Transactions replayed from a journal don't count, they were counted
+/// by the run that applied them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Counters {
+    pub applied: u64,
+    pub ignored: u64,
+    pub rejected: u64,
+    /// Rows that could not be read as a transaction at all.
+    pub malformed: u64,
+}
+
+impl Counters {
+    pub(crate) fn record(&mut self, outcome: &TxOutcome) {
+        match outcome {
+            TxOutcome::Applied => self.applied += 1,
+            TxOutcome::Ignored(_) => self.ignored += 1,
+            TxOutcome::Rejected(_) => self.rejected += 1,
+        }
+    }
+}
+
+impl AddAssign for Counters {
+    fn add_assign(&mut self, other: Self) {
+        self.applied += other.applied;
+        self.ignored += other.ignored;
+        self.rejected += other.rejected;
+        self.malformed += other.malformed;
+    }
+}
+
+/// What happens to disputes, resolutions and chargebacks pointing to a
+/// transaction of another client.
+///
+/// These never have an effect, since clients only ever touch their own
+/// log, but they are a strong hint of fraud, so they get a reason of their
+/// own, `cross_client_dispute`, either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrossClientPolicy {
+    /// Ignore them, like disputes of unknown transactions.
+    #[default]
+    Ignore,
+    /// Reject them, like any other failed transaction.
+    Reject,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown cross-client policy {0:?}, expected ignore or reject")]
+pub struct UnknownCrossClientPolicy(String);
+
+impl FromStr for CrossClientPolicy {
+    type Err = UnknownCrossClientPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "reject" => Ok(Self::Reject),
+            _ => Err(UnknownCrossClientPolicy(s.to_owned())),
+        }
+    }
+}
+
+impl CrossClientPolicy {
+    /// The outcome of a transaction whose ID belongs to another client,
+    /// `owner`.
+    pub(crate) fn outcome(self, tx: &Transaction, owner: ClientId) -> TxOutcome {
+        if tx.is_logged() {
+            return TxOutcome::Rejected(TransactionError::DuplicateTransactionId { owner });
+        }
+
+        let reason = IgnoreReason::CrossClientDispute { owner };
+        match self {
+            Self::Ignore => TxOutcome::Ignored(reason),
+            Self::Reject => TxOutcome::Rejected(TransactionError::Escalated(reason)),
+        }
+    }
+}
+
+impl Default for ClientBook {
+    fn default() -> Self {
+        Self::with_storage(MemoryStorage::default())
+    }
 }
 
 impl ClientBook {
     /// Reads a CSV file from the given path and processes all transactions.
-    pub fn from_csv<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        let mut reader = csv::ReaderBuilder::new()
-            .trim(csv::Trim::All)
-            .from_path(&path)?;
+    ///
+    /// Rows that had no effect, including malformed ones, are reported to stderr.
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_csv_with(path, &IngestOptions::default(), |rejection| {
+            eprintln!("{rejection}");
+            Ok(())
+        })
+    }
+
+    /// Reads a CSV file from the given path and processes all transactions,
+    /// handing every row that had no effect to `on_rejection`.
+    ///
+    /// Processing stops at the first error returned by `on_rejection`.
+    pub fn from_csv_with<P, F>(
+        path: P,
+        options: &IngestOptions,
+        on_rejection: F,
+    ) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Rejection) -> io::Result<()>,
+    {
+        let mut book = ClientBook::default();
+        book.ingest([Input::open(path)], options, on_rejection)?;
+        Ok(book)
+    }
+
+    /// Reads CSV transactions from any reader, such as stdin or an
+    /// in-memory buffer, and processes all of them.
+    ///
+    /// Gzip and zstd compressed input is decoded on the fly.
+    ///
+    /// Rows that had no effect, including malformed ones, are reported to stderr.
+    pub fn from_reader<R: io::Read>(reader: R) -> io::Result<Self> {
+        Self::from_reader_with(reader, &IngestOptions::default(), |rejection| {
+            eprintln!("{rejection}");
+            Ok(())
+        })
+    }
+
+    /// Reads CSV transactions from any reader and processes all of them,
+    /// handing every row that had no effect to `on_rejection`.
+    ///
+    /// Processing stops at the first error returned by `on_rejection`.
+    pub fn from_reader_with<R, F>(
+        reader: R,
+        options: &IngestOptions,
+        on_rejection: F,
+    ) -> io::Result<Self>
+    where
+        R: io::Read,
+        F: FnMut(Rejection) -> io::Result<()>,
+    {
+        let mut book = ClientBook::default();
+        book.ingest([Input::unnamed(reader)], options, on_rejection)?;
+        Ok(book)
+    }
 
+    /// Rebuilds a book from the journal at the given path, creating it
+    /// when missing, and records every transaction applied from now on
+    /// to it, before the book changes.
+    pub fn open_journal<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         let mut book = ClientBook::default();
 
-        for result in reader.deserialize() {
-            let tx: Transaction = result?;
+        // **NOTE:** Only applied transactions are journaled, and applying
+        // them again in the same order must give the same result.
+        let journal = Journal::open(path, |record| {
+            let (outcome, what, client) = match record {
+                Record::Tx(tx) => (
+                    book.apply(tx)?,
+                    format!("transaction {}", tx.id),
+                    tx.client_id,
+                ),
+                Record::Admin(tx) => (
+                    book.administer(tx.client_id, tx.ty, tx.authorization)?,
+                    format!("administrative transaction {}", tx.id),
+                    tx.client_id,
+                ),
+            };
 
-            if let Err(e) = book.append_tx(tx) {
-                eprintln!(
-                    "failed to process transaction {:?} for client {:?}: {e}",
-                    tx.id, tx.client_id
-                );
+            match RejectionReason::from_outcome(outcome) {
+                None => Ok(()),
+                Some(reason) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("journaled {what} for client {client} does not apply: {reason}"),
+                )),
             }
-        }
+        })?;
 
+        book.journal = Some(journal);
+        book.counters = Counters::default();
         Ok(book)
     }
 
+    /// Captures the complete state of this book, see [`Snapshot`].
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: snapshot::VERSION,
+            clients: self
+                .storage
+                .clients()
+                .values()
+                .map(AccountSnapshot::from)
+                .collect(),
+        }
+    }
+
+    /// Restores a book from a snapshot, so that later transactions,
+    /// including disputes of earlier ones, apply as if nothing happened
+    /// in between.
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, SnapshotError> {
+        if snapshot.version != snapshot::VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+
+        let mut clients = IndexMap::with_capacity(snapshot.clients.len());
+        let mut owners = HashMap::new();
+        for account in snapshot.clients {
+            let account = account.restore()?;
+            let id = account.id();
+            if clients.contains_key(&id) {
+                return Err(SnapshotError::DuplicateClient(id));
+            }
+
+            for entry in account.log() {
+                let tx = entry.tx.id;
+                if let Some(&owner) = owners.get(&tx) {
+                    return Err(SnapshotError::SharedTransaction {
+                        tx,
+                        owner,
+                        client: id,
+                    });
+                }
+                owners.insert(tx, id);
+            }
+
+            clients.insert(id, account);
+        }
+
+        Ok(Self::with_storage(MemoryStorage::new(clients)))
+    }
+
+    /// Same as [`ClientBook::ingest`], but applies transactions on `threads`
+    /// worker threads, each owning the accounts of a shard of clients,
+    /// while the calling thread reads the inputs.
+    ///
+    /// Accounts end up exactly as they would reading on a single thread.
+    /// Rows that had no effect are reported in input order for each client,
+    /// but rows of different clients may be reported out of order.
+    ///
+    /// Journaled books only apply one transaction at a time.
+    pub fn ingest_parallel<'a, I, F>(
+        &mut self,
+        inputs: I,
+        options: &IngestOptions,
+        threads: NonZeroUsize,
+        on_rejection: F,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Input<'a>>,
+        F: FnMut(Rejection) -> io::Result<()>,
+    {
+        if self.journal.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "journaled books cannot be ingested in parallel",
+            ));
+        }
+
+        let clients = mem::take(&mut self.storage).into_clients();
+        let (clients, counters, result) = parallel::read(
+            clients,
+            self.cross_client,
+            inputs,
+            options,
+            threads,
+            on_rejection,
+        );
+
+        self.storage = MemoryStorage::new(clients);
+        self.counters += counters;
+        result
+    }
+
+    pub fn into_clients(self) -> IndexMap<ClientId, ClientAccount> {
+        self.storage.into_clients()
+    }
+}
+
+impl<S: Storage> ClientBook<S> {
+    /// An empty book keeping its accounts in `storage`.
+    pub fn with_storage(storage: S) -> Self {
+        Self {
+            storage,
+            journal: None,
+            cross_client: CrossClientPolicy::default(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Sets what happens to disputes of other clients' transactions.
+    pub fn set_cross_client_policy(&mut self, policy: CrossClientPolicy) {
+        self.cross_client = policy;
+    }
+
+    /// How many transactions this book processed so far.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Counts a row that could not be read as a transaction.
+    pub(crate) fn count_malformed(&mut self) {
+        self.counters.malformed += 1;
+    }
+
+    /// Reads transactions from all inputs into this book, handing every
+    /// row that had no effect to `on_rejection`.
+    ///
+    /// Inputs are CSV, unless [`IngestOptions::format`] or their file
+    /// extension say otherwise.
+    ///
+    /// Inputs are read one after the other, unless
+    /// [`IngestOptions::sequence_column`] is set, in which case their rows
+    /// are merged by it.
+    pub fn ingest<'a, I, F>(
+        &mut self,
+        inputs: I,
+        options: &IngestOptions,
+        on_rejection: F,
+    ) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Input<'a>>,
+        F: FnMut(Rejection) -> io::Result<()>,
+    {
+        ingest::read(self, inputs, options, on_rejection)
+    }
+
     /// Appends one transaction to the log and updates the related client's
     /// account. If this is a new client, create one.
-    pub fn append_tx(&mut self, tx: Transaction) -> Result<(), TransactionError> {
-        let client = self
-            .clients
-            .entry(tx.client_id)
-            .or_insert_with(|| ClientAccount::new(tx.client_id));
+    ///
+    /// Transaction IDs are unique across the whole book, a deposit or
+    /// withdrawal reusing the ID of another client's transaction is
+    /// rejected, naming that client. Disputes, resolutions and chargebacks
+    /// of another client's transaction are handled according to the
+    /// [`CrossClientPolicy`].
+    ///
+    /// When the book has a journal, applied transactions are recorded
+    /// there first, and failing to do so leaves the book untouched.
+    ///
+    /// Administrative transactions are rejected as `admin_only`, they only
+    /// apply through [`ClientBook::administer`].
+    pub fn append_tx(&mut self, tx: Transaction) -> io::Result<TxOutcome> {
+        self.apply(tx)
+    }
+
+    /// Appends an administrative transaction of type `ty` ordered by an
+    /// operator, such as unlocking an account risk reviewed, see
+    /// [`Authorization`]. The account numbers it in its audit trail, see
+    /// [`ClientAccount::audit`].
+    ///
+    /// Unlike other transactions, these never create a client, they are
+    /// ignored as `unknown_client` instead, so closing a client that never
+    /// existed doesn't keep it from ever showing up.
+    ///
+    /// **NOTE:** Only the admin interface and journals get here, inputs
+    /// and the public servers go through [`ClientBook::append_tx`].
+    pub fn administer(
+        &mut self,
+        client: ClientId,
+        ty: TransactionType,
+        authorization: Authorization,
+    ) -> io::Result<TxOutcome> {
+        if !ty.is_admin() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not an administrative transaction", ty.name()),
+            ));
+        }
 
-        client.append_tx(tx)
+        let outcome = match self.storage.account(client)? {
+            None => TxOutcome::Ignored(IgnoreReason::UnknownClient),
+            Some(_) => {
+                let journal = &mut self.journal;
+                self.storage.update(client, None, |account| match journal {
+                    Some(journal) => account.administer_with(ty, authorization, |tx| {
+                        journal.append(&Record::Admin(tx.clone()))
+                    }),
+                    None => Ok(account.administer(ty, authorization)),
+                })?
+            }
+        };
+
+        self.counters.record(&outcome);
+        Ok(outcome)
     }
 
-    pub fn into_clients(self) -> IndexMap<ClientId, ClientAccount> {
-        self.clients
+    fn apply(&mut self, tx: Transaction) -> io::Result<TxOutcome> {
+        // **NOTE:** Anything within the same client is left to the account,
+        // which checks it along with everything else it knows about.
+        let outcome = match self.storage.owner(tx.id)? {
+            Some(owner) if owner != tx.client_id => self.cross_client.outcome(&tx, owner),
+            _ => {
+                let journal = &mut self.journal;
+                self.storage
+                    .update(tx.client_id, Some(tx.id), |client| match journal {
+                        Some(journal) => {
+                            client.append_tx_with(tx, |tx| journal.append(&Record::Tx(*tx)))
+                        }
+                        None => Ok(client.append_tx(tx)),
+                    })?
+            }
+        };
+
+        self.counters.record(&outcome);
+        Ok(outcome)
+    }
+
+    /// Looks up a logged transaction of a client along with its dispute state.
+    pub fn logged_tx(&self, client: ClientId, tx: TransactionId) -> io::Result<Option<LogEntry>> {
+        self.storage.logged(client, tx)
+    }
+
+    /// A client's log, if the client is known. See [`Storage::log`].
+    pub fn log(&self, client: ClientId) -> io::Result<Option<Vec<LogEntry>>> {
+        self.storage.log(client)
+    }
+
+    /// A client's balances and status, if the client is known. See
+    /// [`Storage::account`].
+    pub fn account(&self, client: ClientId) -> io::Result<Option<ClientAccount>> {
+        self.storage.account(client)
+    }
+
+    /// Every transaction in dispute. See [`Storage::disputes`].
+    pub fn disputes(&self) -> io::Result<Vec<(ClientId, TransactionId, Decimal)>> {
+        self.storage.disputes()
+    }
+
+    /// Hands every account, along with its full log, to `f`, in the order
+    /// clients were first seen.
+    pub fn for_each_account(
+        &self,
+        mut f: impl FnMut(&ClientAccount) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.storage.for_each_account(&mut f)
+    }
+
+    /// All accounts, in the order clients were first seen. See
+    /// [`Storage::into_accounts`].
+    pub fn into_accounts(self) -> io::Result<IndexMap<ClientId, ClientAccount>> {
+        self.storage.into_accounts()
     }
 }
@@ -1,33 +1,499 @@
-use std::env;
+use std::{
+    env, fs, io,
+    net::TcpListener,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixListener, path::PathBuf};
 
-use anyhow::{Context, Result, anyhow};
-use payx::ClientBook;
+use indexmap::IndexMap;
+
+use anyhow::{Context, Result, anyhow, bail};
+#[cfg(unix)]
+use payx::admin;
+#[cfg(feature = "http")]
+use payx::http;
+use payx::{
+    ClientBook, CrossClientPolicy,
+    client::ClientAccount,
+    ingest::{self, IngestOptions, Input, InputFormat},
+    output::{self, OutputFormat},
+    schema::Schema,
+    server,
+    snapshot::Snapshot,
+    storage::Storage,
+    transaction::ClientId,
+};
+#[cfg(feature = "sqlite")]
+use payx::{export, storage::SqliteStorage};
+
+/// Command line arguments.
+///
+/// ```text
+/// payx [--strict] [--rejects <path>] [--sequence-column <name>]
+///      [--cross-client-disputes ignore|reject] [--threads <n>]
+///      [--serve <addr>] [--http <addr>] [--admin <socket>]
+///      [--admin-snapshots <dir>]
+///      [--load-snapshot <path>] [--save-snapshot <path>] [--journal <path>] [--store <path>]
+///      [--export-sqlite <path>]
+///      [--schema <path>] [--input-format csv|ndjson] [--output-format csv|json|ndjson]
+///      [<input>... | -]
+/// ```
+struct Args {
+    /// Input files or directories, read from stdin when missing or `-`.
+    ///
+    /// Files apply in the order given, and files in directories in the
+    /// order of their names, unless merged by a sequence column.
+    inputs: Vec<String>,
+    /// Where to write rows that had no effect, as CSV.
+    rejects: Option<String>,
+    /// Aborts on the first malformed row.
+    strict: bool,
+    /// Merges rows from all inputs by this column.
+    sequence_column: Option<String>,
+    /// What happens to disputes of other clients' transactions.
+    cross_client: CrossClientPolicy,
+    /// Applies transactions on this many threads, sharded by client.
+    threads: Option<NonZeroUsize>,
+    /// Once inputs are read, keeps serving the book on this address.
+    serve: Option<String>,
+    /// Once inputs are read, keeps serving the book over HTTP on this address.
+    #[cfg(feature = "http")]
+    http: Option<String>,
+    /// Once inputs are read, keeps serving admin commands on this Unix socket.
+    #[cfg(unix)]
+    admin: Option<String>,
+    /// Where admins may save snapshots, by name.
+    #[cfg(unix)]
+    admin_snapshots: Option<String>,
+    /// A snapshot to resume processing from.
+    load_snapshot: Option<String>,
+    /// Where to save the book's snapshot once all input is processed.
+    save_snapshot: Option<String>,
+    /// Where to journal applied transactions, rebuilding the book from
+    /// it at startup.
+    journal: Option<String>,
+    /// An SQLite database keeping the book on disk, across runs.
+    #[cfg(feature = "sqlite")]
+    store: Option<String>,
+    /// Where to export the final accounts and their logs, as an SQLite database.
+    #[cfg(feature = "sqlite")]
+    export_sqlite: Option<String>,
+    /// A TOML file describing the layout of CSV inputs.
+    schema: Option<String>,
+    /// Overrides the input format guessed from file extensions.
+    input_format: Option<InputFormat>,
+    /// How accounts are written to stdout.
+    output_format: OutputFormat,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut inputs = vec![];
+        let mut rejects = None;
+        let mut strict = false;
+        let mut sequence_column = None;
+        let mut cross_client = CrossClientPolicy::default();
+        let mut threads = None;
+        let mut serve = None;
+        #[cfg(feature = "http")]
+        let mut http = None;
+        #[cfg(unix)]
+        let mut admin = None;
+        #[cfg(unix)]
+        let mut admin_snapshots = None;
+        let mut load_snapshot = None;
+        let mut save_snapshot = None;
+        let mut journal = None;
+        #[cfg(feature = "sqlite")]
+        let mut store = None;
+        #[cfg(feature = "sqlite")]
+        let mut export_sqlite = None;
+        let mut schema = None;
+        let mut input_format = None;
+        let mut output_format = OutputFormat::default();
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--rejects" => {
+                    rejects = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--rejects expects a path"))?,
+                    );
+                }
+                "--strict" => strict = true,
+                "--sequence-column" => {
+                    sequence_column = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--sequence-column expects a column name"))?,
+                    );
+                }
+                "--cross-client-disputes" => {
+                    cross_client = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--cross-client-disputes expects ignore or reject"))?
+                        .parse()?;
+                }
+                "--threads" => {
+                    threads = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--threads expects a number"))?
+                            .parse()
+                            .context("--threads expects a positive number")?,
+                    );
+                }
+                "--serve" => {
+                    serve = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--serve expects an address"))?,
+                    );
+                }
+                #[cfg(feature = "http")]
+                "--http" => {
+                    http = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--http expects an address"))?,
+                    );
+                }
+                #[cfg(unix)]
+                "--admin" => {
+                    admin = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--admin expects a socket path"))?,
+                    );
+                }
+                #[cfg(unix)]
+                "--admin-snapshots" => {
+                    admin_snapshots = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--admin-snapshots expects a directory"))?,
+                    );
+                }
+                "--load-snapshot" => {
+                    load_snapshot = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--load-snapshot expects a path"))?,
+                    );
+                }
+                "--save-snapshot" => {
+                    save_snapshot = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--save-snapshot expects a path"))?,
+                    );
+                }
+                "--journal" => {
+                    journal = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--journal expects a path"))?,
+                    );
+                }
+                #[cfg(feature = "sqlite")]
+                "--store" => {
+                    store = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--store expects a path"))?,
+                    );
+                }
+                #[cfg(feature = "sqlite")]
+                "--export-sqlite" => {
+                    export_sqlite = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--export-sqlite expects a path"))?,
+                    );
+                }
+                "--schema" => {
+                    schema = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--schema expects a path"))?,
+                    );
+                }
+                "--input-format" => {
+                    input_format = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("--input-format expects csv or ndjson"))?
+                            .parse()?,
+                    );
+                }
+                "--output-format" => {
+                    output_format = args
+                        .next()
+                        .ok_or_else(|| anyhow!("--output-format expects csv, json or ndjson"))?
+                        .parse()?;
+                }
+                _ if arg.starts_with("--") => bail!("unexpected argument {arg:?}"),
+                _ => inputs.push(arg),
+            }
+        }
+
+        Ok(Self {
+            inputs,
+            rejects,
+            strict,
+            sequence_column,
+            cross_client,
+            threads,
+            serve,
+            #[cfg(feature = "http")]
+            http,
+            #[cfg(unix)]
+            admin,
+            #[cfg(unix)]
+            admin_snapshots,
+            load_snapshot,
+            save_snapshot,
+            journal,
+            #[cfg(feature = "sqlite")]
+            store,
+            #[cfg(feature = "sqlite")]
+            export_sqlite,
+            schema,
+            input_format,
+            output_format,
+        })
+    }
+}
 
 fn main() -> Result<()> {
-    let path = env::args()
-        .nth(1)
-        .ok_or_else(|| anyhow!("missing input CSV file argument"))?;
+    let args = Args::parse()?;
+
+    let options = IngestOptions {
+        strict: args.strict,
+        sequence_column: args.sequence_column,
+        format: args.input_format,
+        schema: match &args.schema {
+            Some(path) => Schema::load(path).context("failed to load schema")?,
+            None => Schema::default(),
+        },
+    };
+
+    let mut rejects = match &args.rejects {
+        Some(path) => {
+            let mut rejects = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_path(path)
+                .with_context(|| format!("failed to create rejects file {path:?}"))?;
+            rejects.write_record(["type", "client", "tx", "amount", "source", "line", "reason"])?;
+            Some(rejects)
+        }
+        None => None,
+    };
+
+    let on_rejection = |rejection| {
+        match &mut rejects {
+            Some(rejects) => rejects.serialize(rejection)?,
+            None => eprintln!("{rejection}"),
+        }
+        Ok(())
+    };
+
+    // **NOTE:** Servers never finish, so nothing that happens at the end
+    // of a run can be combined with them.
+    let server = [
+        ("--serve", &args.serve),
+        #[cfg(feature = "http")]
+        ("--http", &args.http),
+        #[cfg(unix)]
+        ("--admin", &args.admin),
+    ]
+    .into_iter()
+    .find_map(|(flag, addr)| addr.as_ref().map(|_| flag));
+    match server {
+        Some(flag) if args.save_snapshot.is_some() => {
+            bail!("{flag} never finishes, use --journal rather than --save-snapshot")
+        }
+        #[cfg(feature = "sqlite")]
+        Some(flag) if args.export_sqlite.is_some() => {
+            bail!("{flag} never finishes, it cannot be combined with --export-sqlite")
+        }
+        _ => {}
+    }
+
+    #[cfg(unix)]
+    if args.admin_snapshots.is_some() && args.admin.is_none() {
+        bail!("--admin-snapshots is only used by --admin");
+    }
+
+    let listeners = Listeners {
+        tcp: listen(args.serve.as_deref())?,
+        #[cfg(feature = "http")]
+        http: listen(args.http.as_deref())?,
+        #[cfg(unix)]
+        admin: args.admin.as_deref().map(listen_unix).transpose()?,
+        #[cfg(unix)]
+        admin_snapshots: args.admin_snapshots.map(PathBuf::from),
+    };
+
+    let inputs = match args.inputs.as_slice() {
+        [] if server.is_some() => vec![],
+        [] => vec![Input::named("stdin", io::stdin().lock())],
+        [path] if path == "-" => vec![Input::named("stdin", io::stdin().lock())],
+        paths if paths.iter().any(|path| path == "-") => {
+            bail!("stdin cannot be mixed with other inputs")
+        }
+        paths => ingest::collect_files(paths)?
+            .into_iter()
+            .map(Input::open)
+            .collect(),
+    };
+
+    if args.threads.is_some() && args.journal.is_some() {
+        bail!("a journal records transactions one at a time, it cannot be combined with --threads");
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.store {
+        if args.threads.is_some() {
+            bail!(
+                "--store applies transactions one at a time, it cannot be combined with --threads"
+            );
+        }
+        if args.load_snapshot.is_some() || args.save_snapshot.is_some() || args.journal.is_some() {
+            bail!(
+                "--store already keeps the book across runs, it cannot be combined with snapshots or a journal"
+            );
+        }
+
+        let storage =
+            SqliteStorage::open(path).with_context(|| format!("failed to open store {path:?}"))?;
+        let mut book = ClientBook::with_storage(storage);
+        book.set_cross_client_policy(args.cross_client);
+        book.ingest(inputs, &options, on_rejection)
+            .context("failed to process input")?;
+
+        if let Some(path) = &args.export_sqlite {
+            export::sqlite(&book, path).with_context(|| format!("failed to export to {path:?}"))?;
+        }
+
+        if server.is_some() {
+            return serve(rejects, listeners, book);
+        }
+
+        let clients = book.into_accounts().context("failed to read accounts")?;
+        return finish(rejects, args.output_format, clients);
+    }
+
+    let mut book = match (&args.load_snapshot, &args.journal) {
+        (Some(_), Some(_)) => bail!("a snapshot cannot be loaded along with a journal"),
+        (None, Some(path)) => ClientBook::open_journal(path)
+            .with_context(|| format!("failed to open journal {path:?}"))?,
+        (Some(path), None) => {
+            let file = fs::File::open(path)
+                .with_context(|| format!("failed to open snapshot {path:?}"))?;
+            let snapshot = Snapshot::read(file)
+                .with_context(|| format!("failed to read snapshot {path:?}"))?;
+            ClientBook::from_snapshot(snapshot)
+                .with_context(|| format!("failed to restore snapshot {path:?}"))?
+        }
+        (None, None) => ClientBook::default(),
+    };
+    book.set_cross_client_policy(args.cross_client);
+    match args.threads {
+        Some(threads) => book.ingest_parallel(inputs, &options, threads, on_rejection),
+        None => book.ingest(inputs, &options, on_rejection),
+    }
+    .context("failed to process input")?;
+
+    if let Some(path) = &args.save_snapshot {
+        book.snapshot()
+            .save(path)
+            .with_context(|| format!("failed to save snapshot {path:?}"))?;
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.export_sqlite {
+        export::sqlite(&book, path).with_context(|| format!("failed to export to {path:?}"))?;
+    }
+
+    if server.is_some() {
+        return serve(rejects, listeners, book);
+    }
+
+    finish(rejects, args.output_format, book.into_clients())
+}
+
+/// Where the book is served, once inputs are read.
+struct Listeners {
+    /// The line protocol, see [`server::serve`].
+    tcp: Option<TcpListener>,
+    /// The HTTP/JSON API, see [`http::serve`].
+    #[cfg(feature = "http")]
+    http: Option<TcpListener>,
+    /// Admin commands, see [`admin::serve`].
+    #[cfg(unix)]
+    admin: Option<UnixListener>,
+    /// Where admins may save snapshots.
+    #[cfg(unix)]
+    admin_snapshots: Option<PathBuf>,
+}
+
+fn listen(addr: Option<&str>) -> Result<Option<TcpListener>> {
+    addr.map(|addr| TcpListener::bind(addr).with_context(|| format!("failed to listen on {addr}")))
+        .transpose()
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str) -> Result<UnixListener> {
+    admin::listen(path.as_ref()).with_context(|| format!("failed to listen on {path:?}"))
+}
+
+/// Flushes the rejects file and serves the book until a server fails.
+fn serve<S>(
+    rejects: Option<csv::Writer<fs::File>>,
+    listeners: Listeners,
+    book: ClientBook<S>,
+) -> Result<()>
+where
+    S: Storage + Send + 'static,
+{
+    if let Some(mut rejects) = rejects {
+        rejects.flush().context("failed to flush rejects file")?;
+    }
 
-    let book = ClientBook::from_csv(path)?;
+    // **NOTE:** Every server shares the book, and runs on its own thread
+    // until it fails, which ends the whole process.
+    let book = Arc::new(Mutex::new(book));
+    let (failed, failure) = mpsc::channel();
 
-    let mut writer = csv::WriterBuilder::new()
-        // **NOTE:** `Decimal` does not play along nicely with `csv`s
-        // serde implementation when infering the headers,
-        // so I have to explicitly write them as the first record.
-        .has_headers(false)
-        .delimiter(b',')
-        .flexible(false)
-        .from_writer(std::io::stdout());
+    if let Some(listener) = listeners.tcp {
+        eprintln!("listening on {}", listener.local_addr()?);
+        let (failed, book) = (failed.clone(), Arc::clone(&book));
+        thread::spawn(move || failed.send(server::serve(listener, book)));
+    }
 
-    writer.write_record(["client", "available", "held", "total", "locked"])?;
+    #[cfg(feature = "http")]
+    if let Some(listener) = listeners.http {
+        eprintln!("serving HTTP on {}", listener.local_addr()?);
+        let (failed, book) = (failed.clone(), Arc::clone(&book));
+        thread::spawn(move || failed.send(http::serve(listener, book)));
+    }
 
-    for client in book.into_clients().values() {
-        writer
-            .serialize(client)
-            .context("failed to write client row")?;
+    #[cfg(unix)]
+    if let Some(listener) = listeners.admin {
+        let (failed, book) = (failed.clone(), Arc::clone(&book));
+        let snapshots = listeners.admin_snapshots;
+        thread::spawn(move || failed.send(admin::serve(listener, book, snapshots)));
     }
 
-    writer.flush().context("failed to flush writes to stdout")?;
+    drop(failed);
+    failure
+        .recv()
+        .context("no server to run")?
+        .context("server failed")
+}
+
+/// Flushes the rejects file and writes all accounts to stdout.
+fn finish(
+    rejects: Option<csv::Writer<fs::File>>,
+    format: OutputFormat,
+    clients: IndexMap<ClientId, ClientAccount>,
+) -> Result<()> {
+    if let Some(mut rejects) = rejects {
+        rejects.flush().context("failed to flush rejects file")?;
+    }
 
-    Ok(())
+    output::write_accounts(io::stdout().lock(), format, clients.values())
+        .context("failed to write accounts to stdout")
 }
@@ -0,0 +1,129 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use crate::client::ClientAccount;
+
+/// How account snapshots are written.
+///
+/// All formats share the same fields, and decimals are always written as
+/// strings with 4 decimal places, so consumers in other languages don't
+/// go through floats and lose precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// A single JSON array holding all accounts.
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown output format {0:?}, expected csv, json or ndjson")]
+pub struct UnknownOutputFormat(String);
+
+impl FromStr for OutputFormat {
+    type Err = UnknownOutputFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(UnknownOutputFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Writes the snapshot of every account to `writer`.
+pub fn write_accounts<'a, W, I>(writer: W, format: OutputFormat, accounts: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a ClientAccount>,
+{
+    let mut writer = io::BufWriter::new(writer);
+
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                // **NOTE:** `Decimal` does not play along nicely with `csv`s
+                // serde implementation when infering the headers,
+                // so I have to explicitly write them as the first record.
+                .has_headers(false)
+                .delimiter(b',')
+                .flexible(false)
+                .from_writer(writer);
+
+            writer.write_record(["client", "available", "held", "total", "locked", "status"])?;
+
+            for account in accounts {
+                writer.serialize(account)?;
+            }
+
+            writer.flush()
+        }
+        OutputFormat::Json => {
+            writer.write_all(b"[")?;
+
+            for (idx, account) in accounts.into_iter().enumerate() {
+                if idx > 0 {
+                    writer.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut writer, account)?;
+            }
+
+            writer.write_all(b"]\n")?;
+            writer.flush()
+        }
+        OutputFormat::Ndjson => {
+            for account in accounts {
+                serde_json::to_writer(&mut writer, account)?;
+                writer.write_all(b"\n")?;
+            }
+
+            writer.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientBook;
+
+    fn written(format: OutputFormat) -> String {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\ndeposit,2,2,0.1\n";
+        let book = ClientBook::from_reader(input.as_bytes()).expect("input is valid");
+
+        let mut out = vec![];
+        write_accounts(&mut out, format, book.into_clients().values()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_keeps_decimals_as_fixed_strings() {
+        assert_eq!(
+            written(OutputFormat::Json),
+            concat!(
+                r#"[{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false,"status":"active"},"#,
+                r#"{"client":2,"available":"0.1000","held":"0.0000","total":"0.1000","locked":false,"status":"active"}]"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn ndjson_writes_one_account_per_line() {
+        assert_eq!(
+            written(OutputFormat::Ndjson),
+            concat!(
+                r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false,"status":"active"}"#,
+                "\n",
+                r#"{"client":2,"available":"0.1000","held":"0.0000","total":"0.1000","locked":false,"status":"active"}"#,
+                "\n"
+            )
+        );
+    }
+}
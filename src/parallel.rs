@@ -0,0 +1,320 @@
+use std::{collections::HashMap, io, mem, num::NonZeroUsize, sync::mpsc, thread};
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    ClientBook, Counters, CrossClientPolicy,
+    client::ClientAccount,
+    ingest::{self, IngestOptions, Input, Row, Sink},
+    rejection::{Rejection, RejectionReason},
+    storage::{self, MemoryStorage, shard},
+    transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// How many jobs are handed to a worker at once.
+const BATCH: usize = 256;
+
+/// How many batches may wait for a worker before the reader blocks.
+const BACKLOG: usize = 64;
+
+/// What a worker is asked to do.
+enum Job {
+    Append(Transaction, Row),
+    /// Asks whether a client logged a transaction, once every job sent
+    /// before this one is done.
+    Logged {
+        client: ClientId,
+        tx: TransactionId,
+        reply: mpsc::SyncSender<bool>,
+    },
+}
+
+/// Reads transactions from all `inputs` into `clients`, on a worker
+/// thread per shard of clients, handing back the resulting accounts
+/// and how many transactions were processed, even if reading failed
+/// midway.
+///
+/// **NOTE:** Accounts never look at each other, so as long as each one
+/// sees its transactions in the input order, the result is the same as
+/// reading everything on a single thread. The only thing shared is the
+/// book-wide transaction index, which the reader keeps, see [`Router`].
+pub(crate) fn read<'a, I, F>(
+    clients: IndexMap<ClientId, ClientAccount>,
+    cross_client: CrossClientPolicy,
+    inputs: I,
+    options: &IngestOptions,
+    threads: NonZeroUsize,
+    mut on_rejection: F,
+) -> (IndexMap<ClientId, ClientAccount>, Counters, io::Result<()>)
+where
+    I: IntoIterator<Item = Input<'a>>,
+    F: FnMut(Rejection) -> io::Result<()>,
+{
+    let threads = threads.get();
+    let owners = storage::owners(&clients);
+    let order = clients.keys().copied().collect();
+
+    let mut shards: Vec<IndexMap<_, _>> = (0..threads).map(|_| IndexMap::new()).collect();
+    for (id, account) in clients {
+        shards[shard(id, threads)].insert(id, account);
+    }
+
+    thread::scope(|scope| {
+        let (rejected, rejections) = mpsc::channel();
+        let mut senders = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+
+        for clients in shards {
+            let (sender, jobs) = mpsc::sync_channel(BACKLOG);
+            let rejected = rejected.clone();
+
+            let mut book = ClientBook::with_storage(MemoryStorage::new(clients));
+            book.set_cross_client_policy(cross_client);
+
+            workers.push(scope.spawn(move || work(book, jobs, rejected)));
+            senders.push(sender);
+        }
+        drop(rejected);
+
+        let mut router = Router {
+            batches: (0..threads).map(|_| Vec::with_capacity(BATCH)).collect(),
+            senders,
+            rejections,
+            owners,
+            order,
+            cross_client,
+            counters: Counters::default(),
+        };
+
+        let mut result = ingest::read(&mut router, inputs, options, &mut on_rejection);
+        if result.is_ok() {
+            result = (0..threads).try_for_each(|shard| router.flush(shard));
+        }
+
+        // Workers stop once they run out of jobs, rejections keep coming
+        // until the last one does.
+        let Router {
+            senders,
+            rejections,
+            order,
+            mut counters,
+            ..
+        } = router;
+        drop(senders);
+
+        if result.is_ok() {
+            result = rejections.iter().try_for_each(&mut on_rejection);
+        }
+
+        let mut shards = Vec::with_capacity(threads);
+        for worker in workers {
+            let (book, worked) = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            counters += book.counters();
+            shards.push(book.into_clients());
+            if let Err(err) = worked {
+                result = Err(err);
+            }
+        }
+
+        let clients = order
+            .into_iter()
+            .filter_map(|id| Some((id, shards[shard(id, threads)].swap_remove(&id)?)))
+            .collect();
+
+        (clients, counters, result)
+    })
+}
+
+/// Applies jobs to the worker's shard of the book, until the reader is
+/// done or a job fails. The book is handed back either way, with every
+/// job applied before the failure.
+fn work(
+    mut book: ClientBook,
+    jobs: mpsc::Receiver<Vec<Job>>,
+    rejected: mpsc::Sender<Rejection>,
+) -> (ClientBook, io::Result<()>) {
+    let result = jobs.iter().flatten().try_for_each(|job| {
+        // **NOTE:** Sends only fail once the reader gave up, in which
+        // case nobody is listening anymore.
+        match job {
+            Job::Append(tx, row) => {
+                if let Some(reason) = RejectionReason::from_outcome(book.append_tx(tx)?) {
+                    let _ = rejected.send(row.rejection(reason));
+                }
+            }
+            Job::Logged { client, tx, reply } => {
+                let _ = reply.send(book.logged_tx(client, tx)?.is_some());
+            }
+        }
+        Ok(())
+    });
+
+    (book, result)
+}
+
+/// Routes transactions read by the reader thread to the workers.
+struct Router {
+    senders: Vec<mpsc::SyncSender<Vec<Job>>>,
+    /// Jobs not yet handed to each worker.
+    batches: Vec<Vec<Job>>,
+    rejections: mpsc::Receiver<Rejection>,
+    /// The last client that tried to log each transaction ID.
+    owners: HashMap<TransactionId, ClientId>,
+    /// Clients in the order they were first seen.
+    order: IndexSet<ClientId>,
+    cross_client: CrossClientPolicy,
+    /// Transactions handled without reaching a worker.
+    counters: Counters,
+}
+
+impl Router {
+    /// Hands the pending jobs of a shard to its worker.
+    fn flush(&mut self, shard: usize) -> io::Result<()> {
+        if self.batches[shard].is_empty() {
+            return Ok(());
+        }
+
+        let batch = mem::replace(&mut self.batches[shard], Vec::with_capacity(BATCH));
+        self.senders[shard]
+            .send(batch)
+            .map_err(|_| worker_stopped())
+    }
+
+    /// Whether a client logged a transaction, waiting for everything
+    /// routed to it so far to apply.
+    fn logged(&mut self, client: ClientId, tx: TransactionId) -> io::Result<bool> {
+        let shard = shard(client, self.senders.len());
+        let (reply, answer) = mpsc::sync_channel(1);
+
+        self.batches[shard].push(Job::Logged { client, tx, reply });
+        self.flush(shard)?;
+        answer.recv().map_err(|_| worker_stopped())
+    }
+}
+
+impl Sink for Router {
+    fn append(
+        &mut self,
+        tx: Transaction,
+        row: Row,
+        on_rejection: &mut dyn FnMut(Rejection) -> io::Result<()>,
+    ) -> io::Result<()> {
+        while let Ok(rejection) = self.rejections.try_recv() {
+            on_rejection(rejection)?;
+        }
+
+        // **NOTE:** Only workers know whether a transaction applied, so
+        // the reader only asks them when a client uses an ID another client
+        // tried to log. Upstream IDs are unique, so this is rare.
+        if let Some(&owner) = self.owners.get(&tx.id)
+            && owner != tx.client_id
+            && self.logged(owner, tx.id)?
+        {
+            let outcome = self.cross_client.outcome(&tx, owner);
+            self.counters.record(&outcome);
+
+            let reason = RejectionReason::from_outcome(outcome)
+                .expect("transactions of other clients never apply");
+            return on_rejection(row.rejection(reason));
+        }
+
+        if tx.is_logged() {
+            self.owners.insert(tx.id, tx.client_id);
+        }
+        self.order.insert(tx.client_id);
+
+        let shard = shard(tx.client_id, self.senders.len());
+        self.batches[shard].push(Job::Append(tx, row));
+        if self.batches[shard].len() == BATCH {
+            self.flush(shard)?;
+        }
+
+        Ok(())
+    }
+
+    fn malformed(&mut self) {
+        self.counters.malformed += 1;
+    }
+}
+
+fn worker_stopped() -> io::Error {
+    io::Error::other("worker thread stopped unexpectedly")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+
+    use super::*;
+
+    /// Rows for a few clients, with disputes, failed withdrawals, and IDs
+    /// reused across clients, both before and after they applied.
+    fn input() -> String {
+        let mut input = "type,client,tx,amount\n".to_owned();
+        for i in 0..2_000u32 {
+            let client = i % 7;
+            let row = match i % 11 {
+                0..=4 => format!("deposit,{client},{i},{}.5", i % 13),
+                5 | 6 => format!("withdrawal,{client},{i},{}", i % 17),
+                7 => format!("dispute,{client},{},", i - 7),
+                8 => format!("resolve,{client},{},", i - 8),
+                9 => format!("chargeback,{client},{},", i.saturating_sub(14)),
+                _ if i % 2 == 0 => format!("deposit,{},{},1.0", client + 1, i - 5),
+                _ => format!("dispute,{},{},", client + 1, i - 5),
+            };
+            writeln!(input, "{row}").unwrap();
+        }
+        input
+    }
+
+    fn rejections(book: &mut ClientBook, threads: Option<usize>) -> Vec<(String, u64, String)> {
+        let input = input();
+        let inputs = [Input::unnamed(input.as_bytes())];
+        let options = IngestOptions::default();
+
+        let mut rejections = vec![];
+        let on_rejection = |rejection: Rejection| {
+            rejections.push((
+                rejection.client,
+                rejection.line,
+                rejection.reason.to_string(),
+            ));
+            Ok(())
+        };
+
+        match threads.and_then(NonZeroUsize::new) {
+            Some(threads) => book.ingest_parallel(inputs, &options, threads, on_rejection),
+            None => book.ingest(inputs, &options, on_rejection),
+        }
+        .expect("input is valid");
+
+        rejections.sort();
+        rejections
+    }
+
+    #[test]
+    fn matches_a_single_thread() {
+        let mut single = ClientBook::default();
+        let expected = rejections(&mut single, None);
+        let expected_accounts = single.snapshot();
+        assert!(
+            expected
+                .iter()
+                .any(|(_, _, reason)| reason.contains("belongs to client"))
+        );
+
+        for threads in [1, 3, 8] {
+            let mut sharded = ClientBook::default();
+            assert_eq!(rejections(&mut sharded, Some(threads)), expected);
+
+            let accounts = sharded.snapshot();
+            assert_eq!(
+                serde_json::to_value(&accounts).unwrap(),
+                serde_json::to_value(&expected_accounts).unwrap(),
+                "{threads} threads"
+            );
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use serde::ser::SerializeStruct;
+
+use crate::client::TxOutcome;
+
+/// An input row that had no effect on the book.
+///
+/// The row is kept as it was read, rather than as a [`crate::transaction::Transaction`],
+/// so that whoever looks into it later sees exactly what the partner sent.
+#[derive(Debug)]
+pub struct Rejection {
+    /// The input the row was read from, usually a file path.
+    pub source: Option<String>,
+    /// The line the row starts at in the input.
+    pub line: u64,
+    pub ty: String,
+    pub client: String,
+    pub tx: String,
+    pub amount: String,
+    pub reason: RejectionReason,
+}
+
+/// Why a row had no effect.
+#[derive(Debug, thiserror::Error)]
+pub enum RejectionReason {
+    /// The row could not be read as a transaction.
+    #[error("malformed row: {0}")]
+    Malformed(String),
+    /// The row was read as a transaction, which was ignored or rejected.
+    /// Never [`TxOutcome::Applied`], see [`RejectionReason::from_outcome`].
+    #[error("{0}")]
+    NoEffect(TxOutcome),
+}
+
+impl RejectionReason {
+    /// The reason for a transaction outcome, if it had no effect.
+    pub fn from_outcome(outcome: TxOutcome) -> Option<Self> {
+        (!outcome.is_applied()).then_some(Self::NoEffect(outcome))
+    }
+
+    /// A stable, machine-readable code for this reason.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Malformed(_) => "malformed",
+            Self::NoEffect(outcome) => outcome.code(),
+        }
+    }
+}
+
+impl Rejection {
+    /// Builds a rejection from a CSV record, picking the transaction
+    /// fields by their header names.
+    pub(crate) fn from_record(
+        source: Option<&str>,
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+        reason: RejectionReason,
+    ) -> Self {
+        let field = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .and_then(|idx| record.get(idx))
+                .unwrap_or_default()
+                .to_owned()
+        };
+
+        Self {
+            source: source.map(str::to_owned),
+            line: record.position().map_or(0, |pos| pos.line()),
+            ty: field("type"),
+            client: field("client"),
+            tx: field("tx"),
+            amount: field("amount"),
+            reason,
+        }
+    }
+
+    /// Builds a rejection from an NDJSON line, picking the transaction
+    /// fields by their keys. Lines that are not valid JSON leave them empty.
+    pub(crate) fn from_json(
+        source: Option<&str>,
+        line: u64,
+        value: Option<&serde_json::Value>,
+        reason: RejectionReason,
+    ) -> Self {
+        let field = |name: &str| match value.and_then(|value| value.get(name)) {
+            Some(serde_json::Value::String(field)) => field.clone(),
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(field) => field.to_string(),
+        };
+
+        Self {
+            source: source.map(str::to_owned),
+            line,
+            ty: field("type"),
+            client: field("client"),
+            tx: field("tx"),
+            amount: field("amount"),
+            reason,
+        }
+    }
+}
+
+impl Rejection {
+    /// Where the row is, as `source:line`, or `line N` for unnamed inputs.
+    pub fn location(&self) -> String {
+        match &self.source {
+            Some(source) => format!("{source}:{}", self.line),
+            None => format!("line {}", self.line),
+        }
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let location = self.location();
+        let action = match self.reason {
+            RejectionReason::Malformed(_) => return write!(f, "{location}: {}", self.reason),
+            RejectionReason::NoEffect(TxOutcome::Ignored(_)) => "ignored",
+            RejectionReason::NoEffect(_) => "failed to process",
+        };
+
+        write!(
+            f,
+            "{location}: {action} transaction {} for client {}: {}",
+            self.tx, self.client, self.reason
+        )
+    }
+}
+
+impl serde::Serialize for Rejection {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // **NOTE:** The reason is written as its code rather than its message,
+        // so consumers don't break when messages are reworded.
+        let mut ser = serializer.serialize_struct("Rejection", 7)?;
+        ser.serialize_field("type", &self.ty)?;
+        ser.serialize_field("client", &self.client)?;
+        ser.serialize_field("tx", &self.tx)?;
+        ser.serialize_field("amount", &self.amount)?;
+        ser.serialize_field("source", &self.source)?;
+        ser.serialize_field("line", &self.line)?;
+        ser.serialize_field("reason", self.reason.code())?;
+        ser.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TransactionError;
+
+    #[test]
+    fn picks_fields_by_header_and_writes_reason_code() {
+        let headers = csv::StringRecord::from(vec!["client", "type", "amount", "tx"]);
+        let record = csv::StringRecord::from(vec!["2", "withdrawal", "5.0", "7"]);
+
+        let rejection = Rejection::from_record(
+            Some("in.csv"),
+            &headers,
+            &record,
+            RejectionReason::NoEffect(TransactionError::NotEnoughBalance.into()),
+        );
+
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(vec![]);
+        writer.serialize(&rejection).unwrap();
+
+        let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(written, "withdrawal,2,7,5.0,in.csv,0,not_enough_balance\n");
+    }
+}
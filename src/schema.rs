@@ -0,0 +1,194 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::Deserialize;
+
+/// How a partner lays out their CSV files.
+///
+/// The default is our own layout: comma delimited, with a header row
+/// naming the `type`, `client`, `tx` and `amount` columns. A partner's
+/// layout is declared in TOML, for example:
+///
+/// ```toml
+/// delimiter = ";"
+///
+/// [columns]
+/// client = "client_id"
+/// tx = "txn_id"
+///
+/// [types]
+/// DEPOSIT = "deposit"
+/// refund = "withdrawal"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schema {
+    /// The field delimiter, a single ASCII character.
+    pub delimiter: char,
+    /// The quote character, a single ASCII character.
+    pub quote: char,
+    /// Whether quotes are interpreted at all. When disabled, quote
+    /// characters are read as any other character.
+    pub quoting: bool,
+    /// The column names in order, for files without a header row.
+    /// When absent, the first row holds them.
+    pub headers: Option<Vec<String>>,
+    /// The names this layout uses for the transaction columns.
+    pub columns: Columns,
+    /// Maps type spellings used by this layout to ours.
+    pub types: HashMap<String, TypeName>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            quote: '"',
+            quoting: true,
+            headers: None,
+            columns: Columns::default(),
+            types: HashMap::new(),
+        }
+    }
+}
+
+/// The names of the transaction columns in a layout.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Columns {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub client: String,
+    pub tx: String,
+    pub amount: String,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            ty: "type".to_owned(),
+            client: "client".to_owned(),
+            tx: "tx".to_owned(),
+            amount: "amount".to_owned(),
+        }
+    }
+}
+
+/// One of our transaction types, as a type alias can point to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypeName {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl TypeName {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+        }
+    }
+}
+
+impl Schema {
+    /// Reads a schema from the TOML file at the given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        Self::from_toml(&fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+
+    /// Reads a schema from TOML.
+    pub fn from_toml(toml: &str) -> io::Result<Self> {
+        let schema: Self = toml::from_str(toml)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.message().to_owned()))?;
+
+        ascii(schema.delimiter, "delimiter")?;
+        ascii(schema.quote, "quote")?;
+        Ok(schema)
+    }
+
+    pub(crate) fn delimiter(&self) -> u8 {
+        self.delimiter as u8
+    }
+
+    pub(crate) fn quote(&self) -> u8 {
+        self.quote as u8
+    }
+
+    /// Translates a column name of this layout to ours, leaving
+    /// unknown columns, such as sequence numbers, as they are.
+    pub(crate) fn canonical_column<'a>(&self, name: &'a str) -> &'a str {
+        let columns = &self.columns;
+        match name {
+            _ if name == columns.ty => "type",
+            _ if name == columns.client => "client",
+            _ if name == columns.tx => "tx",
+            _ if name == columns.amount => "amount",
+            _ => name,
+        }
+    }
+
+    /// Translates a type spelling of this layout to ours.
+    pub(crate) fn canonical_type<'a>(&self, ty: &'a str) -> &'a str {
+        self.types.get(ty).map_or(ty, |ty| ty.as_str())
+    }
+}
+
+fn ascii(c: char, name: &str) -> io::Result<()> {
+    if c.is_ascii() {
+        return Ok(());
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{name} must be a single ASCII character, got {c:?}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_missing_settings_with_defaults() {
+        let schema = Schema::from_toml(
+            r#"
+            delimiter = ";"
+
+            [columns]
+            client = "client_id"
+
+            [types]
+            DEPOSIT = "deposit"
+            "#,
+        )
+        .expect("schema is valid");
+
+        assert_eq!(schema.delimiter(), b';');
+        assert_eq!(schema.quote(), b'"');
+        assert_eq!(schema.canonical_column("client_id"), "client");
+        assert_eq!(schema.canonical_column("tx"), "tx");
+        assert_eq!(schema.canonical_type("DEPOSIT"), "deposit");
+        assert_eq!(schema.canonical_type("deposit"), "deposit");
+    }
+
+    #[test]
+    fn rejects_invalid_schemas() {
+        for toml in [
+            r#"delimiter = "€""#,
+            r#"delimiter = ";;""#,
+            r#"separator = ";""#,
+            "[types]\nrefund = \"payout\"",
+        ] {
+            let err = Schema::from_toml(toml).expect_err(toml);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{toml}");
+        }
+    }
+}
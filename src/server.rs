@@ -0,0 +1,209 @@
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crate::{ClientBook, client::TxOutcome, ingest, storage::Storage, transaction::ClientId};
+
+/// Accepts connections on `listener` until it fails, serving each one
+/// on its own thread, all of them sharing `book`.
+///
+/// The protocol is line based. Clients send transactions as CSV rows
+/// without a header, `type,client,tx,amount`, and every row gets a line
+/// back, one of:
+///
+/// ```text
+/// applied
+/// ignored <code> <message>
+/// rejected <code> <message>
+/// malformed <message>
+/// ```
+///
+/// Codes are the same as in the rejects file. `query <client>` answers
+/// with the client's account, as in the CSV output, or `unknown_client`:
+///
+/// ```text
+/// account <client>,<available>,<held>,<total>,<locked>,<status>
+/// unknown_client <client>
+/// ```
+///
+/// Lines longer than [`MAX_LINE`] bytes are answered with `malformed line
+/// too long`, and lines that aren't UTF-8 with `malformed line is not
+/// valid UTF-8`. Anything going wrong on the server's side, such as
+/// failing to write to the journal, is answered with `error <message>`.
+pub fn serve<S>(listener: TcpListener, book: Arc<Mutex<ClientBook<S>>>) -> io::Result<()>
+where
+    S: Storage + Send + 'static,
+{
+    loop {
+        let (stream, peer) = listener.accept()?;
+        let book = Arc::clone(&book);
+
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, &book) {
+                eprintln!("{peer}: connection failed: {err}");
+            }
+        });
+    }
+}
+
+/// The longest line a client may send, in bytes, not counting its `\n`
+/// or `\r\n` end.
+pub const MAX_LINE: usize = 1024;
+
+/// Answers every line a client sends, until it disconnects.
+fn handle<S: Storage>(stream: TcpStream, book: &Mutex<ClientBook<S>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = vec![];
+
+    loop {
+        line.clear();
+        // **NOTE:** Reading is capped right past the longest line with its
+        // end, so clients can't make us buffer a line that never ends.
+        let limit = MAX_LINE as u64 + 2;
+        if (&mut reader).take(limit).read_until(b'\n', &mut line)? == 0 {
+            return writer.flush();
+        }
+
+        if !line.ends_with(b"\n") && line.len() as u64 == limit {
+            skip_line(&mut reader)?;
+        }
+
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        let response = if content.len() > MAX_LINE {
+            Some("malformed line too long".to_owned())
+        } else {
+            match str::from_utf8(content) {
+                Ok(content) => respond(content.trim(), book),
+                Err(_) => Some("malformed line is not valid UTF-8".to_owned()),
+            }
+        };
+
+        if let Some(response) = response {
+            writeln!(writer, "{response}")?;
+        }
+
+        // **NOTE:** Clients sending many lines at once get their answers
+        // in as few writes as possible, and are never kept waiting.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+}
+
+/// Drops what's left of a line that was too long, up to its end.
+fn skip_line<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        match buf.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None if buf.is_empty() => return Ok(()),
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// The answer to a single line, if it needs one.
+fn respond<S: Storage>(line: &str, book: &Mutex<ClientBook<S>>) -> Option<String> {
+    if line.is_empty() {
+        return None;
+    }
+
+    let Ok(mut book) = book.lock() else {
+        return Some("error book is unavailable after an earlier failure".to_owned());
+    };
+
+    if let Some(client) = line.strip_prefix("query ") {
+        let Ok(client) = client.trim().parse().map(ClientId::new) else {
+            return Some(format!("malformed invalid client {:?}", client.trim()));
+        };
+
+        return Some(match book.account(client) {
+            Ok(Some(account)) => {
+                let mut row = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(vec![]);
+                row.serialize(&account).expect("accounts always serialize");
+
+                let row = row.into_inner().expect("writing to memory never fails");
+                format!("account {}", String::from_utf8_lossy(&row).trim_end())
+            }
+            Ok(None) => format!("unknown_client {client}"),
+            Err(err) => format!("error {err}"),
+        });
+    }
+
+    let tx = match ingest::parse_row(line) {
+        Ok(tx) => tx,
+        Err(message) => {
+            book.count_malformed();
+            return Some(format!("malformed {message}"));
+        }
+    };
+
+    Some(match book.append_tx(tx) {
+        Ok(TxOutcome::Applied) => "applied".to_owned(),
+        Ok(outcome @ TxOutcome::Ignored(_)) => format!("ignored {} {outcome}", outcome.code()),
+        Ok(outcome) => format!("rejected {} {outcome}", outcome.code()),
+        Err(err) => format!("error {err}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acknowledges_every_line_and_answers_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let book = Arc::new(Mutex::new(ClientBook::default()));
+        thread::spawn(move || serve(listener, book));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"deposit,1,1,10.0\n\
+                  withdrawal,1,2,50.0\n\
+                  \n\
+                  dispute,1,9,\n\
+                  refund,1,3,1.0\n\
+                  withdrawal, 1, 4, 2.5\n\
+                  query 1\n\
+                  query 2\n",
+            )
+            .unwrap();
+        stream.write_all(&[b'x'; 3 * MAX_LINE]).unwrap();
+        stream.write_all(b"\ndeposit,1,5,\xff\nquery 1\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let lines: Vec<_> = BufReader::new(stream)
+            .lines()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], "applied");
+        assert_eq!(
+            lines[1],
+            "rejected not_enough_balance not enough balance to withdraw"
+        );
+        assert!(lines[2].starts_with("ignored unknown_target "));
+        assert!(lines[3].starts_with("malformed "));
+        assert_eq!(lines[4], "applied");
+        assert_eq!(lines[5], "account 1,7.5000,0.0000,7.5000,false,active");
+        assert_eq!(lines[6], "unknown_client 2");
+        assert_eq!(lines[7], "malformed line too long");
+        assert_eq!(lines[8], "malformed line is not valid UTF-8");
+        assert_eq!(lines[9], lines[5], "connection survives bad lines");
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Read, Write},
+    ops::Neg,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::{AccountStatus, ClientAccount, DisputeState, LogEntry},
+    transaction::{AdminTx, ClientId, Transaction, TransactionId, TransactionType},
+};
+
+/// The snapshot layout version written by this build.
+///
+/// **NOTE:** Bump it whenever the layout changes in a way older
+/// snapshots can't be read with, so they fail loudly instead of
+/// restoring something subtly wrong.
+pub const VERSION: u32 = 1;
+
+/// The complete state of a [`crate::ClientBook`], so that processing can
+/// resume in a later run, as if all previous input was read again.
+///
+/// Unlike the account output, this holds every account's log and the
+/// state of its disputes, and decimals are kept with full precision.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub clients: Vec<AccountSnapshot>,
+}
+
+/// The state of a single [`crate::client::ClientAccount`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccountSnapshot {
+    pub client: ClientId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub locked: bool,
+    pub status: AccountStatus,
+    /// Logged transactions, in the order they were applied.
+    pub log: Vec<EntrySnapshot>,
+    /// Administrative transactions, in the order they were applied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audit: Vec<AdminTx>,
+}
+
+/// A logged transaction along with its dispute state.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EntrySnapshot {
+    pub tx: Transaction,
+    pub dispute: DisputeState,
+}
+
+impl From<&LogEntry> for EntrySnapshot {
+    fn from(entry: &LogEntry) -> Self {
+        Self {
+            tx: entry.tx,
+            dispute: entry.dispute,
+        }
+    }
+}
+
+impl From<&ClientAccount> for AccountSnapshot {
+    /// Captures everything needed to restore `account` in a later run.
+    fn from(account: &ClientAccount) -> Self {
+        Self {
+            client: account.id(),
+            available: account.available(),
+            held: account.held(),
+            locked: account.locked(),
+            status: account.status(),
+            log: account.log().map(EntrySnapshot::from).collect(),
+            audit: account.audit().to_vec(),
+        }
+    }
+}
+
+impl AccountSnapshot {
+    /// Restores the account this snapshot was taken of.
+    ///
+    /// **NOTE:** Balances are stored along with the log, rather than
+    /// replaying it, because replaying would need every dispute-related
+    /// transaction too. They must still agree with what the log says,
+    /// or the snapshot was tampered with or written by a buggy build.
+    pub fn restore(self) -> Result<ClientAccount, SnapshotError> {
+        let client = self.client;
+
+        let mut available = Decimal::ZERO;
+        let mut held = Decimal::ZERO;
+        let mut ids = HashSet::with_capacity(self.log.len());
+
+        for EntrySnapshot { tx, dispute } in &self.log {
+            if tx.client_id != client {
+                return Err(SnapshotError::InvalidEntry { client, tx: tx.id });
+            }
+
+            // A dispute holds, and a chargeback reverses, part of the
+            // original amount, never nothing or more than all of it.
+            if let (
+                TransactionType::Deposit { amount } | TransactionType::Withdrawal { amount },
+                DisputeState::Disputed { held: part } | DisputeState::ChargedBack { amount: part },
+            ) = (tx.ty, *dispute)
+                && (part <= Decimal::ZERO || part > amount)
+            {
+                return Err(SnapshotError::InvalidEntry { client, tx: tx.id });
+            }
+
+            // Mirrors how each dispute state moved balances in `TxDiff`.
+            available += match (tx.ty, *dispute) {
+                (TransactionType::Deposit { amount }, DisputeState::Disputed { held }) => {
+                    amount - held
+                }
+                (
+                    TransactionType::Deposit { amount },
+                    DisputeState::ChargedBack { amount: reversed },
+                ) => amount - reversed,
+                (TransactionType::Deposit { amount }, _) => amount,
+                (
+                    TransactionType::Withdrawal { amount },
+                    DisputeState::ChargedBack { amount: reversed },
+                ) => reversed - amount,
+                (TransactionType::Withdrawal { amount }, _) => amount.neg(),
+                _ => return Err(SnapshotError::InvalidEntry { client, tx: tx.id }),
+            };
+            held += dispute.held();
+
+            if !ids.insert(tx.id) {
+                return Err(SnapshotError::DuplicateTransaction { client, tx: tx.id });
+            }
+        }
+
+        if available != self.available || held != self.held {
+            return Err(SnapshotError::BalanceMismatch(client));
+        }
+
+        // Administrative transactions are numbered by their position.
+        for (idx, tx) in self.audit.iter().enumerate() {
+            if tx.client_id != client || !tx.ty.is_admin() || tx.id as usize != idx + 1 {
+                return Err(SnapshotError::InvalidAuditEntry { client, id: tx.id });
+            }
+        }
+
+        let status = self.status;
+        if (status == AccountStatus::Locked) != self.locked {
+            return Err(SnapshotError::StatusMismatch(client));
+        }
+
+        let entries = self.log.into_iter().map(|entry| LogEntry {
+            tx: entry.tx,
+            dispute: entry.dispute,
+        });
+        Ok(ClientAccount::from_storage(
+            client, available, held, status, entries, self.audit,
+        ))
+    }
+}
+
+/// Why a snapshot can't be restored.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("unsupported snapshot version {0}, expected {VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("client {0} appears more than once")]
+    DuplicateClient(ClientId),
+    #[error("transaction {tx} appears more than once in the log of client {client}")]
+    DuplicateTransaction { client: ClientId, tx: TransactionId },
+    #[error("transaction {tx} of client {client} was already used by client {owner}")]
+    SharedTransaction {
+        tx: TransactionId,
+        owner: ClientId,
+        client: ClientId,
+    },
+    #[error("transaction {tx} cannot be in the log of client {client}")]
+    InvalidEntry { client: ClientId, tx: TransactionId },
+    #[error("administrative transaction {id} cannot be in the audit trail of client {client}")]
+    InvalidAuditEntry { client: ClientId, id: u32 },
+    #[error("balances of client {0} do not match its log")]
+    BalanceMismatch(ClientId),
+    #[error("status of client {0} does not match whether it is locked")]
+    StatusMismatch(ClientId),
+}
+
+impl Snapshot {
+    /// Reads a snapshot written by [`Snapshot::write`].
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        Ok(serde_json::from_reader(io::BufReader::new(reader))?)
+    }
+
+    /// Writes this snapshot as JSON.
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+
+    /// Writes this snapshot to the given path.
+    ///
+    /// The snapshot is written next to its destination first, so that a
+    /// crash midway never leaves a truncated snapshot behind for the next run.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        let file = fs::File::create(&tmp)?;
+        self.write(&file)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::{
+        ClientBook,
+        ingest::{IngestOptions, Input},
+        transaction::Authorization,
+    };
+
+    fn book(input: &str) -> ClientBook {
+        ClientBook::from_reader(input.as_bytes()).expect("input is valid")
+    }
+
+    fn roundtrip(book: &ClientBook) -> Result<ClientBook, SnapshotError> {
+        let mut written = vec![];
+        book.snapshot().write(&mut written).unwrap();
+        ClientBook::from_snapshot(Snapshot::read(written.as_slice()).unwrap())
+    }
+
+    #[test]
+    fn resumes_disputes_of_earlier_runs() {
+        let first = book(
+            "type,client,tx,amount\n\
+             deposit,1,1,10.0\n\
+             deposit,1,2,5.5\n\
+             dispute,1,2,1.5\n\
+             deposit,2,3,3.0\n\
+             withdrawal,2,4,1.0\n\
+             dispute,2,4,\n",
+        );
+
+        let mut resumed = roundtrip(&first).expect("snapshot is consistent");
+        let second = "type,client,tx,amount\nresolve,1,2,\ndispute,1,1,\nchargeback,2,4,\n";
+        resumed
+            .ingest(
+                [Input::unnamed(second.as_bytes())],
+                &IngestOptions::default(),
+                |rejection| panic!("{rejection}"),
+            )
+            .expect("input is valid");
+
+        let clients = resumed.into_clients();
+        assert_eq!(clients[0].available(), dec!(5.5));
+        assert_eq!(clients[0].held(), dec!(10.0));
+        assert_eq!(clients[1].available(), dec!(3.0));
+        assert!(clients[1].held().is_zero());
+    }
+
+    #[test]
+    fn keeps_audit_trails() {
+        let mut book = book("type,client,tx,amount\ndeposit,1,1,10.0\n");
+        let client = ClientId::new(1);
+        let authorization = Authorization {
+            operator: "risk-7".to_owned(),
+            reason: String::new(),
+        };
+        let outcome = book.administer(client, TransactionType::Freeze, authorization.clone());
+        assert!(outcome.unwrap().is_applied());
+
+        let mut resumed = roundtrip(&book).expect("snapshot is consistent");
+        let outcome = resumed.administer(client, TransactionType::Unfreeze, authorization);
+        assert!(outcome.unwrap().is_applied());
+
+        let ids: Vec<_> = resumed.into_clients()[0]
+            .audit()
+            .iter()
+            .map(|tx| (tx.id, tx.ty.name()))
+            .collect();
+        assert_eq!(ids, [(1, "freeze"), (2, "unfreeze")]);
+
+        let mut snapshot = book.snapshot();
+        snapshot.clients[0].audit[0].id = 2;
+        assert_eq!(
+            ClientBook::from_snapshot(snapshot).err(),
+            Some(SnapshotError::InvalidAuditEntry { client, id: 2 })
+        );
+    }
+
+    #[test]
+    fn refuses_inconsistent_snapshots() {
+        let book = book("type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,5.0\n");
+
+        let mut snapshot = book.snapshot();
+        snapshot.clients[0].available = dec!(20.0);
+        assert_eq!(
+            ClientBook::from_snapshot(snapshot).err(),
+            Some(SnapshotError::BalanceMismatch(ClientId::new(1)))
+        );
+
+        // Balances agree with the log, but a dispute can't hold that much.
+        for held in [dec!(-5.0), dec!(15.0)] {
+            let mut snapshot = book.snapshot();
+            snapshot.clients[0].log[0].dispute = DisputeState::Disputed { held };
+            snapshot.clients[0].available -= held;
+            snapshot.clients[0].held = held;
+            assert!(matches!(
+                ClientBook::from_snapshot(snapshot),
+                Err(SnapshotError::InvalidEntry { .. })
+            ));
+        }
+
+        let mut snapshot = book.snapshot();
+        snapshot.clients[0].status = AccountStatus::Locked;
+        assert_eq!(
+            ClientBook::from_snapshot(snapshot).err(),
+            Some(SnapshotError::StatusMismatch(ClientId::new(1)))
+        );
+
+        let mut snapshot = book.snapshot();
+        snapshot.clients[0].log[1].tx = snapshot.clients[0].log[0].tx;
+        assert!(matches!(
+            ClientBook::from_snapshot(snapshot),
+            Err(SnapshotError::DuplicateTransaction { .. })
+        ));
+
+        let mut snapshot = book.snapshot();
+        let mut other = snapshot.clients[0].clone();
+        other.client = ClientId::new(2);
+        for entry in &mut other.log {
+            entry.tx.client_id = other.client;
+        }
+        snapshot.clients.push(other);
+        assert!(matches!(
+            ClientBook::from_snapshot(snapshot),
+            Err(SnapshotError::SharedTransaction { .. })
+        ));
+
+        let mut snapshot = book.snapshot();
+        snapshot.version += 1;
+        assert!(matches!(
+            ClientBook::from_snapshot(snapshot),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::HashMap, io};
+
+use indexmap::IndexMap;
+use rust_decimal::Decimal;
+
+use crate::{
+    client::{ClientAccount, LogEntry, TxOutcome},
+    transaction::{ClientId, TransactionId},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+/// Where a [`crate::ClientBook`] keeps its accounts and their logs.
+///
+/// **NOTE:** Accounts are only ever handed out for the duration of an
+/// update, rather than as references, so that backends keeping them on
+/// disk don't need to hold everything in memory.
+pub trait Storage {
+    /// Runs `f` on a client's account, creating the account if the client
+    /// is new, and persists the result.
+    ///
+    /// The account handed to `f` has its audit trail, and at least the
+    /// log entry of `tx`, if it has one, which is all a transaction with
+    /// that ID looks at. Administrative transactions don't take an ID.
+    /// Changes are only persisted when `f` applies a transaction.
+    fn update<F>(
+        &mut self,
+        client: ClientId,
+        tx: Option<TransactionId>,
+        f: F,
+    ) -> io::Result<TxOutcome>
+    where
+        F: FnOnce(&mut ClientAccount) -> io::Result<TxOutcome>;
+
+    /// Looks up a logged transaction along with its dispute state.
+    fn logged(&self, client: ClientId, tx: TransactionId) -> io::Result<Option<LogEntry>>;
+
+    /// A client's log, in the order transactions applied, if the client
+    /// is known.
+    fn log(&self, client: ClientId) -> io::Result<Option<Vec<LogEntry>>>;
+
+    /// A client's balances, status and audit trail, if the client is known.
+    ///
+    /// The account comes without its log, which can be much larger than
+    /// what's asked for, see [`Storage::log`] for it.
+    fn account(&self, client: ClientId) -> io::Result<Option<ClientAccount>>;
+
+    /// Every transaction in dispute, as its client, its ID and what it
+    /// holds, in the order clients were first seen, then the order
+    /// transactions applied.
+    fn disputes(&self) -> io::Result<Vec<(ClientId, TransactionId, Decimal)>>;
+
+    /// The client whose log holds a transaction with the given ID, if any.
+    fn owner(&self, tx: TransactionId) -> io::Result<Option<ClientId>>;
+
+    /// Hands every account, along with its full log, to `f`, in the order
+    /// clients were first seen.
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(&ClientAccount) -> io::Result<()>,
+    ) -> io::Result<()>;
+
+    /// All accounts, in the order clients were first seen.
+    ///
+    /// Backends keeping logs on disk may leave them out of the accounts,
+    /// their balances are always complete.
+    fn into_accounts(self) -> io::Result<IndexMap<ClientId, ClientAccount>>
+    where
+        Self: Sized;
+}
+
+/// Keeps everything in memory. The default.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    clients: IndexMap<ClientId, ClientAccount>,
+    /// Which client logged each transaction, across all clients.
+    owners: HashMap<TransactionId, ClientId>,
+}
+
+impl MemoryStorage {
+    pub(crate) fn new(clients: IndexMap<ClientId, ClientAccount>) -> Self {
+        let owners = owners(&clients);
+        Self { clients, owners }
+    }
+
+    pub(crate) fn clients(&self) -> &IndexMap<ClientId, ClientAccount> {
+        &self.clients
+    }
+
+    pub(crate) fn into_clients(self) -> IndexMap<ClientId, ClientAccount> {
+        self.clients
+    }
+}
+
+/// Which client logged each transaction of `clients`.
+pub(crate) fn owners(
+    clients: &IndexMap<ClientId, ClientAccount>,
+) -> HashMap<TransactionId, ClientId> {
+    clients
+        .values()
+        .flat_map(|account| account.log().map(|entry| (entry.tx.id, account.id())))
+        .collect()
+}
+
+/// Which of `shards` owns a client's account, when accounts are split
+/// between threads or tasks.
+pub(crate) fn shard(client: ClientId, shards: usize) -> usize {
+    usize::from(client.get()) % shards
+}
+
+impl Storage for MemoryStorage {
+    fn update<F>(
+        &mut self,
+        client: ClientId,
+        tx: Option<TransactionId>,
+        f: F,
+    ) -> io::Result<TxOutcome>
+    where
+        F: FnOnce(&mut ClientAccount) -> io::Result<TxOutcome>,
+    {
+        let account = self
+            .clients
+            .entry(client)
+            .or_insert_with(|| ClientAccount::new(client));
+
+        let outcome = f(account)?;
+        if outcome.is_applied()
+            && let Some(tx) = tx
+            && account.logged(&tx).is_some()
+        {
+            self.owners.entry(tx).or_insert(client);
+        }
+
+        Ok(outcome)
+    }
+
+    fn logged(&self, client: ClientId, tx: TransactionId) -> io::Result<Option<LogEntry>> {
+        Ok(self
+            .clients
+            .get(&client)
+            .and_then(|account| account.logged(&tx).cloned()))
+    }
+
+    fn log(&self, client: ClientId) -> io::Result<Option<Vec<LogEntry>>> {
+        Ok(self
+            .clients
+            .get(&client)
+            .map(|account| account.log().cloned().collect()))
+    }
+
+    fn account(&self, client: ClientId) -> io::Result<Option<ClientAccount>> {
+        Ok(self.clients.get(&client).map(|account| {
+            ClientAccount::from_storage(
+                client,
+                account.available(),
+                account.held(),
+                account.status(),
+                None,
+                account.audit().to_vec(),
+            )
+        }))
+    }
+
+    fn disputes(&self) -> io::Result<Vec<(ClientId, TransactionId, Decimal)>> {
+        Ok(self
+            .clients
+            .values()
+            .flat_map(|account| {
+                account
+                    .disputes()
+                    .map(|(tx, held)| (account.id(), tx, held))
+            })
+            .collect())
+    }
+
+    fn owner(&self, tx: TransactionId) -> io::Result<Option<ClientId>> {
+        Ok(self.owners.get(&tx).copied())
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(&ClientAccount) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.clients.values().try_for_each(f)
+    }
+
+    fn into_accounts(self) -> io::Result<IndexMap<ClientId, ClientAccount>> {
+        Ok(self.clients)
+    }
+}
@@ -0,0 +1,449 @@
+use std::{io, path::Path, str::FromStr};
+
+use indexmap::IndexMap;
+use rusqlite::{Connection, OptionalExtension, params};
+use rust_decimal::Decimal;
+
+use super::Storage;
+use crate::{
+    client::{AccountStatus, ClientAccount, DisputeState, LogEntry, TxOutcome},
+    transaction::{AdminTx, ClientId, TransactionId},
+};
+
+/// Keeps accounts and their logs in an SQLite database, so histories
+/// larger than memory fit, and the book outlives the process.
+///
+/// Decimals are stored as text, so they keep their exact value. Logged
+/// transactions, their dispute states and each account's audit trail are
+/// stored as JSON.
+///
+/// **NOTE:** Accounts rely on their rowid for the order clients were first
+/// seen, log entries on their `seq` for the order they applied. Audit
+/// trails only hold a handful of administrative transactions, so they are
+/// kept in their account's row, which is read in full anyway.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client    INTEGER NOT NULL UNIQUE,
+        available TEXT    NOT NULL,
+        held      TEXT    NOT NULL,
+        status    TEXT    NOT NULL,
+        audit     TEXT    NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS log (
+        seq     INTEGER PRIMARY KEY,
+        client  INTEGER NOT NULL,
+        tx      INTEGER NOT NULL,
+        payload TEXT    NOT NULL,
+        dispute TEXT    NOT NULL,
+        UNIQUE (client, tx)
+    );
+
+    CREATE INDEX IF NOT EXISTS log_tx ON log (tx);
+
+    CREATE INDEX IF NOT EXISTS log_disputes ON log (client)
+        WHERE json_extract(dispute, '$.state') = 'disputed';
+";
+
+impl SqliteStorage {
+    /// Opens the database at the given path, creating it when missing.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::init(Connection::open(path).map_err(io::Error::other)?)
+    }
+
+    /// Opens a database that lives in memory, mostly useful for testing.
+    pub fn open_in_memory() -> io::Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(io::Error::other)?)
+    }
+
+    fn init(conn: Connection) -> io::Result<Self> {
+        // **NOTE:** Every transaction is committed on its own, WAL keeps
+        // those commits cheap while still surviving a crash of the process.
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(io::Error::other)?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .map_err(io::Error::other)?;
+        conn.execute_batch(SCHEMA).map_err(io::Error::other)?;
+
+        Ok(Self { conn })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn update<F>(
+        &mut self,
+        client: ClientId,
+        tx: Option<TransactionId>,
+        f: F,
+    ) -> io::Result<TxOutcome>
+    where
+        F: FnOnce(&mut ClientAccount) -> io::Result<TxOutcome>,
+    {
+        let db = self.conn.transaction().map_err(io::Error::other)?;
+
+        let entry = match tx {
+            Some(tx) => logged(&db, client, tx)?,
+            None => None,
+        };
+        let account = account(&db, client, entry)?;
+
+        let is_new = account.is_none();
+        let mut account = account.unwrap_or_else(|| ClientAccount::new(client));
+
+        let outcome = f(&mut account)?;
+
+        // New clients are kept even if nothing applied, just as they are in memory.
+        if is_new || outcome.is_applied() {
+            let audit = serde_json::to_string(account.audit())?;
+            db.prepare_cached(
+                "INSERT INTO accounts (client, available, held, status, audit)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (client) DO UPDATE SET
+                    available = excluded.available,
+                    held = excluded.held,
+                    status = excluded.status,
+                    audit = excluded.audit",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    client.get(),
+                    account.available().to_string(),
+                    account.held().to_string(),
+                    account.status().as_str(),
+                    audit,
+                ])
+            })
+            .map_err(io::Error::other)?;
+        }
+
+        if outcome.is_applied()
+            && let Some(tx) = tx
+            && let Some(entry) = account.logged(&tx)
+        {
+            let payload = serde_json::to_string(&entry.tx)?;
+            let dispute = serde_json::to_string(&entry.dispute)?;
+            db.prepare_cached(
+                "INSERT INTO log (client, tx, payload, dispute) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (client, tx) DO UPDATE SET dispute = excluded.dispute",
+            )
+            .and_then(|mut stmt| stmt.execute(params![client.get(), tx.get(), payload, dispute]))
+            .map_err(io::Error::other)?;
+        }
+
+        db.commit().map_err(io::Error::other)?;
+        Ok(outcome)
+    }
+
+    fn logged(&self, client: ClientId, tx: TransactionId) -> io::Result<Option<LogEntry>> {
+        logged(&self.conn, client, tx)
+    }
+
+    fn log(&self, client: ClientId) -> io::Result<Option<Vec<LogEntry>>> {
+        if self.account(client)?.is_none() {
+            return Ok(None);
+        }
+        log(&self.conn, client).map(Some)
+    }
+
+    fn account(&self, client: ClientId) -> io::Result<Option<ClientAccount>> {
+        account(&self.conn, client, None)
+    }
+
+    fn disputes(&self) -> io::Result<Vec<(ClientId, TransactionId, Decimal)>> {
+        // **NOTE:** Only a handful of transactions are ever in dispute, the
+        // partial index keeps this from scanning the whole log.
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT log.client, log.tx, log.dispute
+                 FROM log JOIN accounts USING (client)
+                 WHERE json_extract(log.dispute, '$.state') = 'disputed'
+                 ORDER BY accounts.rowid, log.seq",
+            )
+            .map_err(io::Error::other)?;
+
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    ClientId::new(row.get(0)?),
+                    TransactionId::new(row.get(1)?),
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(io::Error::other)?;
+
+        rows.map(|row| {
+            let (client, tx, dispute) = row.map_err(io::Error::other)?;
+            match serde_json::from_str(&dispute)? {
+                DisputeState::Disputed { held } => Ok((client, tx, held)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "dispute index out of sync",
+                )),
+            }
+        })
+        .collect()
+    }
+
+    fn owner(&self, tx: TransactionId) -> io::Result<Option<ClientId>> {
+        self.conn
+            .prepare_cached("SELECT client FROM log WHERE tx = ?1 ORDER BY seq LIMIT 1")
+            .and_then(|mut stmt| stmt.query_row([tx.get()], |row| row.get(0)).optional())
+            .map(|client| client.map(ClientId::new))
+            .map_err(io::Error::other)
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(&ClientAccount) -> io::Result<()>,
+    ) -> io::Result<()> {
+        for (client, available, held, status, audit) in self.balances()? {
+            let entries = log(&self.conn, client)?;
+            f(&ClientAccount::from_storage(
+                client, available, held, status, entries, audit,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    fn into_accounts(self) -> io::Result<IndexMap<ClientId, ClientAccount>> {
+        Ok(self
+            .balances()?
+            .into_iter()
+            .map(|(client, available, held, status, audit)| {
+                let account =
+                    ClientAccount::from_storage(client, available, held, status, None, audit);
+                (client, account)
+            })
+            .collect())
+    }
+}
+
+/// An account as stored in the `accounts` table.
+type Balances = (ClientId, Decimal, Decimal, AccountStatus, Vec<AdminTx>);
+
+impl SqliteStorage {
+    /// The balances, statuses and audit trails of all accounts, in the
+    /// order clients were first seen.
+    fn balances(&self) -> io::Result<Vec<Balances>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT client, available, held, status, audit FROM accounts ORDER BY rowid")
+            .map_err(io::Error::other)?;
+
+        stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, u16>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })
+        .map_err(io::Error::other)?
+        .map(|row| {
+            let (client, available, held, status, audit) = row.map_err(io::Error::other)?;
+            Ok((
+                ClientId::new(client),
+                decimal(&available)?,
+                decimal(&held)?,
+                account_status(&status)?,
+                serde_json::from_str(&audit)?,
+            ))
+        })
+        .collect()
+    }
+}
+
+/// A client's account, if the client is known, with only `entry` in its log.
+fn account(
+    conn: &Connection,
+    client: ClientId,
+    entry: Option<LogEntry>,
+) -> io::Result<Option<ClientAccount>> {
+    let row = conn
+        .prepare_cached("SELECT available, held, status, audit FROM accounts WHERE client = ?1")
+        .and_then(|mut stmt| {
+            stmt.query_row([client.get()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .optional()
+        })
+        .map_err(io::Error::other)?;
+
+    row.map(|(available, held, status, audit)| {
+        Ok(ClientAccount::from_storage(
+            client,
+            decimal(&available)?,
+            decimal(&held)?,
+            account_status(&status)?,
+            entry,
+            serde_json::from_str(&audit)?,
+        ))
+    })
+    .transpose()
+}
+
+/// A client's log, in the order transactions applied.
+fn log(conn: &Connection, client: ClientId) -> io::Result<Vec<LogEntry>> {
+    let mut stmt = conn
+        .prepare_cached("SELECT payload, dispute FROM log WHERE client = ?1 ORDER BY seq")
+        .map_err(io::Error::other)?;
+
+    stmt.query_map([client.get()], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })
+    .map_err(io::Error::other)?
+    .map(|row| {
+        let (payload, dispute) = row.map_err(io::Error::other)?;
+        entry(&payload, &dispute)
+    })
+    .collect()
+}
+
+fn logged(conn: &Connection, client: ClientId, tx: TransactionId) -> io::Result<Option<LogEntry>> {
+    let row = conn
+        .prepare_cached("SELECT payload, dispute FROM log WHERE client = ?1 AND tx = ?2")
+        .and_then(|mut stmt| {
+            stmt.query_row(params![client.get(), tx.get()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .optional()
+        })
+        .map_err(io::Error::other)?;
+
+    row.map(|(payload, dispute)| entry(&payload, &dispute))
+        .transpose()
+}
+
+/// A log entry, out of its `payload` and `dispute` columns.
+fn entry(payload: &str, dispute: &str) -> io::Result<LogEntry> {
+    Ok(LogEntry {
+        tx: serde_json::from_str(payload)?,
+        dispute: serde_json::from_str(dispute)?,
+    })
+}
+
+fn decimal(s: &str) -> io::Result<Decimal> {
+    Decimal::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn account_status(s: &str) -> io::Result<AccountStatus> {
+    AccountStatus::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::dec;
+
+    use super::*;
+    use crate::{
+        ClientBook,
+        ingest::{IngestOptions, Input},
+        transaction::{Authorization, TransactionType},
+    };
+
+    const INPUT: &str = "type,client,tx,amount\n\
+                         deposit,2,1,10.0\n\
+                         deposit,1,2,5.0\n\
+                         withdrawal,2,3,4.0\n\
+                         withdrawal,1,4,50.0\n\
+                         dispute,2,1,2.5\n\
+                         deposit,1,3,1.0\n";
+
+    #[test]
+    fn matches_the_memory_storage() {
+        let mut on_disk = ClientBook::with_storage(SqliteStorage::open_in_memory().unwrap());
+        let mut in_memory = ClientBook::default();
+        let mut disputes = vec![];
+
+        for book_input in [
+            INPUT,
+            "type,client,tx,amount\nchargeback,2,1,\ndeposit,2,5,1.0\n",
+        ] {
+            let mut disk_rejections = vec![];
+            on_disk
+                .ingest(
+                    [Input::unnamed(book_input.as_bytes())],
+                    &IngestOptions::default(),
+                    |rejection| {
+                        disk_rejections.push(rejection.reason.code());
+                        Ok(())
+                    },
+                )
+                .unwrap();
+
+            let mut memory_rejections = vec![];
+            in_memory
+                .ingest(
+                    [Input::unnamed(book_input.as_bytes())],
+                    &IngestOptions::default(),
+                    |rejection| {
+                        memory_rejections.push(rejection.reason.code());
+                        Ok(())
+                    },
+                )
+                .unwrap();
+
+            assert_eq!(disk_rejections, memory_rejections);
+            assert_eq!(on_disk.disputes().unwrap(), in_memory.disputes().unwrap());
+            disputes.push(on_disk.disputes().unwrap());
+        }
+
+        assert_eq!(
+            disputes,
+            [
+                vec![(ClientId::new(2), TransactionId::new(1), dec!(2.5))],
+                vec![]
+            ]
+        );
+        assert_eq!(
+            on_disk
+                .logged_tx(ClientId::new(2), TransactionId::new(1))
+                .unwrap()
+                .map(|entry| entry.dispute),
+            Some(DisputeState::ChargedBack { amount: dec!(2.5) })
+        );
+
+        let client = ClientId::new(1);
+        for ty in [TransactionType::Freeze, TransactionType::Unfreeze] {
+            let authorization = Authorization {
+                operator: "risk-7".to_owned(),
+                reason: String::new(),
+            };
+            let outcome = on_disk.administer(client, ty, authorization.clone());
+            assert!(outcome.unwrap().is_applied());
+            let outcome = in_memory.administer(client, ty, authorization);
+            assert!(outcome.unwrap().is_applied());
+        }
+        let audit = on_disk.account(client).unwrap().unwrap().audit().to_vec();
+        let ids: Vec<_> = audit.iter().map(|tx| tx.id).collect();
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(on_disk.log(client).unwrap().unwrap().len(), 1);
+
+        let on_disk = on_disk.into_accounts().unwrap();
+        let in_memory = in_memory.into_clients();
+        assert_eq!(
+            on_disk.keys().collect::<Vec<_>>(),
+            in_memory.keys().collect::<Vec<_>>()
+        );
+
+        for (disk, memory) in on_disk.values().zip(in_memory.values()) {
+            assert_eq!(disk.available(), memory.available());
+            assert_eq!(disk.held(), memory.held());
+            assert_eq!(disk.status(), memory.status());
+            assert_eq!(disk.audit().len(), memory.audit().len());
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// A transaction type.
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum TransactionType {
     Deposit {
@@ -14,7 +14,12 @@ pub enum TransactionType {
     /// Starts a dispute of a transaction.
     ///
     /// [`Transaction::id`] refers to a previous transaction.
-    Dispute,
+    Dispute {
+        /// The disputed portion of the original amount. When absent,
+        /// the dispute covers everything that is not already disputed.
+        #[serde(default, deserialize_with = "optional_amount")]
+        amount: Option<Decimal>,
+    },
     /// Resolves a transaction, freeing the held balance.
     ///
     /// [`Transaction::id`] refers to a previous transaction.
@@ -23,9 +28,61 @@ pub enum TransactionType {
     ///
     /// [`Transaction::id`] refers to a previous transaction.
     Chargeback,
+    /// Freezes an active account, blocking withdrawals.
+    ///
+    /// This and the following are administrative transactions, which
+    /// only apply as an [`AdminTx`], along with an [`Authorization`].
+    Freeze,
+    /// Reactivates a frozen account.
+    Unfreeze,
+    /// Reinstates a locked account, once risk reviewed it.
+    Unlock,
+    /// Closes an account for good. Only accounts without any balance,
+    /// available or held, can be closed.
+    Close,
+}
+
+/// Who ordered an administrative transaction, and why.
+///
+/// Administrative transactions move an account between statuses, see
+/// [`crate::client::AccountStatus`]. They are kept in the account's
+/// audit trail, so this stays on record.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Authorization {
+    /// A reference to the operator who ordered the transaction.
+    /// Administrative transactions without one are rejected.
+    pub operator: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl TransactionType {
+    /// The name of this type, as in the `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Deposit { .. } => "deposit",
+            Self::Withdrawal { .. } => "withdrawal",
+            Self::Dispute { .. } => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Freeze => "freeze",
+            Self::Unfreeze => "unfreeze",
+            Self::Unlock => "unlock",
+            Self::Close => "close",
+        }
+    }
+
+    /// Whether this is an administrative transaction, which only an
+    /// operator may order.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Self::Freeze | Self::Unfreeze | Self::Unlock | Self::Close
+        )
+    }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct Transaction {
     #[serde(flatten)]
     pub ty: TransactionType,
@@ -48,6 +105,68 @@ impl Transaction {
             _ => None,
         }
     }
+
+    /// Whether this transaction is kept in its account's log, and thus
+    /// takes up its ID. Dispute-related transactions only point to one.
+    pub fn is_logged(&self) -> bool {
+        matches!(
+            self.ty,
+            TransactionType::Deposit { .. } | TransactionType::Withdrawal { .. }
+        )
+    }
+}
+
+/// An administrative transaction, as ordered by an operator.
+///
+/// **NOTE:** Transaction IDs belong to partners, so these don't take one.
+/// They are numbered by their account instead, in its audit trail, see
+/// [`crate::client::ClientAccount::audit`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AdminTx {
+    #[serde(flatten)]
+    pub ty: TransactionType,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// The position of this transaction in its account's audit trail,
+    /// counting from 1.
+    pub id: u32,
+    #[serde(flatten)]
+    pub authorization: Authorization,
+}
+
+/// Anything applied to a book, as journals keep it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Record {
+    Tx(Transaction),
+    Admin(AdminTx),
+}
+
+/// Reads an optional amount, treating empty fields as missing.
+///
+/// **NOTE:** CSV rows always carry the `amount` column, so a dispute
+/// without an amount shows up as an empty string rather than a missing
+/// field, which `Decimal` refuses to parse.
+fn optional_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Decimal(Decimal),
+        Other(String),
+    }
+
+    match Option::<Amount>::deserialize(deserializer)? {
+        Some(Amount::Decimal(amount)) => Ok(Some(amount)),
+        Some(Amount::Other(other)) if other.is_empty() => Ok(None),
+        Some(Amount::Other(other)) => Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Str(&other),
+            &"a decimal amount",
+        )),
+        None => Ok(None),
+    }
 }
 
 pub use sealed::{ClientId, TransactionId};
@@ -67,6 +186,16 @@ mod sealed {
         pub fn new(id: u16) -> Self {
             Self(id)
         }
+
+        pub fn get(self) -> u16 {
+            self.0
+        }
+    }
+
+    impl std::fmt::Display for ClientId {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
     }
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
@@ -77,5 +206,15 @@ mod sealed {
         pub fn new(id: u32) -> Self {
             Self(id)
         }
+
+        pub fn get(self) -> u32 {
+            self.0
+        }
+    }
+
+    impl std::fmt::Display for TransactionId {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
     }
 }